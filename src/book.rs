@@ -0,0 +1,102 @@
+//! Opening books in the Polyglot format
+
+use std::{fs, io, path::Path};
+
+use crate::types::{Move, Piece, Square};
+use crate::Position;
+
+/// An entry of a Polyglot book: the key of a position, one of its moves and the weight of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BookEntry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+/// A Polyglot opening book, loaded in memory. The entries are sorted by key as the format
+/// requires, so the moves of a position are found with a binary search on its
+/// [Position::polyglot_key]
+///
+/// ```no_run
+/// use turbochess::{PolyglotBook, Position};
+///
+/// let book = PolyglotBook::open("book.bin").unwrap();
+/// for (mv, weight) in book.lookup(&Position::default()) {
+///     println!("{mv} {weight}");
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>,
+}
+
+impl PolyglotBook {
+    /// Reads a book from a `.bin` file
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+    /// Reads a book from the contents of a `.bin` file: entries of 16 bytes in big endian, with
+    /// the key, the move, the weight and 4 bytes of learning data that are ignored
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if !bytes.len().is_multiple_of(16) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The book size isn't a multiple of 16 bytes",
+            ));
+        }
+        let entries = bytes
+            .chunks_exact(16)
+            .map(|entry| BookEntry {
+                key: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes(entry[8..10].try_into().unwrap()),
+                weight: u16::from_be_bytes(entry[10..12].try_into().unwrap()),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+    /// Gets the number of entries in the book
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Checks if the book has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Gets the book moves of a position with their weights, in the order of the book. Moves
+    /// that aren't legal in the position, from a key collision, are left out
+    pub fn lookup(&self, pos: &Position) -> Vec<(Move, u16)> {
+        let key = pos.polyglot_key();
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| Some((Self::decode(pos, entry.mv)?, entry.weight)))
+            .collect()
+    }
+    /// Decodes a move of the book: the destination file and rank in bits 0-5, the start file
+    /// and rank in bits 6-11 and the promotion piece in bits 12-14. Castling is written as the
+    /// king taking its own rook
+    fn decode(pos: &Position, raw: u16) -> Option<Move> {
+        let square = |bits: u16| (bits >> 3 & 7) as usize * 8 + (bits & 7) as usize;
+        let from = square(raw >> 6);
+        let mut to = square(raw);
+        let promo = match raw >> 12 & 7 {
+            0 => None,
+            1 => Some(Piece::KNIGHT),
+            2 => Some(Piece::BISHOP),
+            3 => Some(Piece::ROOK),
+            4 => Some(Piece::QUEEN),
+            _ => return None,
+        };
+        if pos.piece_on(from) == Some(Piece::KING) {
+            to = match (from, to) {
+                (Square::E1, Square::H1) => Square::G1,
+                (Square::E1, Square::A1) => Square::C1,
+                (Square::E8, Square::H8) => Square::G8,
+                (Square::E8, Square::A8) => Square::C8,
+                _ => to,
+            };
+        }
+        pos.move_from_squares(from, to, promo)
+    }
+}
@@ -0,0 +1,127 @@
+//! C bindings, enabled with the `capi` feature. Build the crate with
+//! `cargo rustc --lib --release --features capi --crate-type cdylib` (or `staticlib`) to link it
+//! from C or C++
+//!
+//! Moves are passed as the packed `u16` of [Move::to_raw]
+//!
+//! # Ownership and threading
+//!
+//! A position is an opaque pointer created by [tc_position_new] or [tc_position_from_fen] and
+//! owned by the caller, who must release it exactly once with [tc_position_free]. Every other
+//! function borrows it for the duration of the call only, so it must be a live pointer returned
+//! by one of the constructors. A position can be moved to another thread, but it must not be used
+//! from several threads at the same time; use one position per thread instead.
+//!
+//! ```c
+//! TcPosition *pos = tc_position_new();
+//! uint16_t moves[218];
+//! size_t count = tc_legal(pos, moves, 218);
+//! tc_make(pos, moves[0]);
+//! char fen[128];
+//! tc_fen(pos, fen, sizeof fen);
+//! tc_undo(pos, moves[0]);
+//! tc_position_free(pos);
+//! ```
+
+use alloc::boxed::Box;
+use core::ffi::{c_char, CStr};
+use core::str::FromStr;
+
+use crate::types::Move;
+use crate::Position;
+
+/// Opaque position handle
+pub type TcPosition = Position;
+
+/// Creates the initial position
+#[no_mangle]
+pub extern "C" fn tc_position_new() -> *mut TcPosition {
+    Box::into_raw(Box::default())
+}
+
+/// Creates a position from a nul-terminated FEN. Returns null if the FEN is invalid
+///
+/// # Safety
+///
+/// `fen` must be null or point to a nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn tc_position_from_fen(fen: *const c_char) -> *mut TcPosition {
+    if fen.is_null() {
+        return core::ptr::null_mut();
+    }
+    let Ok(fen) = CStr::from_ptr(fen).to_str() else {
+        return core::ptr::null_mut();
+    };
+    match Position::from_str(fen) {
+        Ok(pos) => Box::into_raw(Box::new(pos)),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Frees a position. Null is ignored
+///
+/// # Safety
+///
+/// `pos` must be null or a position from one of the constructors that wasn't freed yet
+#[no_mangle]
+pub unsafe extern "C" fn tc_position_free(pos: *mut TcPosition) {
+    if !pos.is_null() {
+        drop(Box::from_raw(pos));
+    }
+}
+
+/// Writes up to `cap` legal moves into `out` and returns the number of legal moves, which can be
+/// greater than `cap`. A buffer of 218 moves is always enough
+///
+/// # Safety
+///
+/// `pos` must be a live position and `out` must be valid for `cap` writes
+#[no_mangle]
+pub unsafe extern "C" fn tc_legal(pos: *const TcPosition, out: *mut u16, cap: usize) -> usize {
+    let legal = (*pos).legal();
+    for (i, mv) in legal.into_iter().take(cap).enumerate() {
+        *out.add(i) = mv.to_raw();
+    }
+    legal.count()
+}
+
+/// Makes a move if it's legal. Returns whether it was made
+///
+/// # Safety
+///
+/// `pos` must be a live position
+#[no_mangle]
+pub unsafe extern "C" fn tc_make(pos: *mut TcPosition, mv: u16) -> bool {
+    (*pos).try_make_move(Move::from_raw(mv)).is_ok()
+}
+
+/// Undoes a move if it's the last one made. Returns whether it was undone
+///
+/// # Safety
+///
+/// `pos` must be a live position
+#[no_mangle]
+pub unsafe extern "C" fn tc_undo(pos: *mut TcPosition, mv: u16) -> bool {
+    let pos = &mut *pos;
+    if pos.moves.last() != Some(&Move::from_raw(mv)) {
+        return false;
+    }
+    pos.undo().is_some()
+}
+
+/// Writes the FEN into `buf` as a nul-terminated string, truncated to fit `len` bytes, and returns
+/// the length of the whole FEN without the nul. 128 bytes are always enough
+///
+/// # Safety
+///
+/// `pos` must be a live position and `buf` must be valid for `len` writes
+#[no_mangle]
+pub unsafe extern "C" fn tc_fen(pos: *const TcPosition, buf: *mut c_char, len: usize) -> usize {
+    let fen = (*pos).fen();
+    if len > 0 {
+        let n = fen.len().min(len - 1);
+        core::ptr::copy_nonoverlapping(fen.as_ptr(), buf as *mut u8, n);
+        *buf.add(n) = 0;
+    }
+    fen.len()
+}
@@ -0,0 +1,158 @@
+//! A game of chess: a [Position] along with the moves played and the result
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::types::{Color, Move, MoveError};
+use crate::Position;
+
+/// Result of a game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameResult {
+    /// The game hasn't finished yet
+    Ongoing,
+    /// White checkmated black
+    WhiteWins,
+    /// Black checkmated white
+    BlackWins,
+    /// Stalemate, threefold repetition, fifty move rule or dead position
+    Draw,
+}
+
+impl GameResult {
+    /// Gets the result of a position on its own, with the repetitions of its history
+    pub fn of(pos: &Position) -> Self {
+        let turn = pos.actual_state().turn;
+        if pos.is_checkmate() {
+            if turn == Color::WHITE {
+                Self::BlackWins
+            } else {
+                Self::WhiteWins
+            }
+        } else if pos.is_stalemate()
+            || pos.actual_state().hm >= 100
+            || pos.repetition_count() >= 3
+            || pos.is_dead()
+        {
+            Self::Draw
+        } else {
+            Self::Ongoing
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    /// Writes the result as in a PGN
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameResult::Ongoing => write!(f, "*"),
+            GameResult::WhiteWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+        }
+    }
+}
+
+/// A game being played from a start position. Only legal moves can be pushed, and the result is
+/// updated after every move
+///
+/// ```
+/// use turbochess::{game::{Game, GameResult}, types::{Move, Square}};
+///
+/// let mut game = Game::new();
+/// game.push(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)).unwrap();
+/// assert_eq!(game.san_moves().collect::<Vec<_>>(), ["e4"]);
+/// assert_eq!(game.result(), GameResult::Ongoing);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Game {
+    start: Position,
+    pos: Position,
+    moves: Vec<(Move, String)>,
+    result: GameResult,
+}
+
+impl Game {
+    /// Creates a game from the initial position
+    pub fn new() -> Self {
+        Self::from_position(&Position::default())
+    }
+    /// Creates a game starting from a position. Its history isn't part of the game
+    pub fn from_position(pos: &Position) -> Self {
+        let start = pos.fork();
+        Self {
+            pos: start.clone(),
+            result: GameResult::of(&start),
+            start,
+            moves: Vec::new(),
+        }
+    }
+    /// Gets the current position
+    pub fn position(&self) -> &Position {
+        &self.pos
+    }
+    /// Gets the moves played, in order
+    pub fn moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.moves.iter().map(|(mv, _)| *mv)
+    }
+    /// Gets the moves played in SAN, in order
+    pub fn san_moves(&self) -> impl Iterator<Item = &str> {
+        self.moves.iter().map(|(_, san)| san.as_str())
+    }
+    /// Gets the result of the game after the last move
+    pub fn result(&self) -> GameResult {
+        self.result
+    }
+    /// Plays a move after checking that it's legal. If it isn't, the game is left untouched
+    pub fn push(&mut self, mv: Move) -> Result<(), MoveError> {
+        self.pos.check_move(mv)?;
+        let san = self.pos.to_san(mv);
+        self.pos.make_move(mv);
+        self.moves.push((mv, san));
+        self.result = GameResult::of(&self.pos);
+        Ok(())
+    }
+    /// Takes back the last move and returns it, or `None` at the start of the game
+    pub fn pop(&mut self) -> Option<Move> {
+        let (mv, _) = self.moves.pop()?;
+        self.pos.undo_move(mv);
+        self.result = GameResult::of(&self.pos);
+        Some(mv)
+    }
+    /// Writes the game as a PGN. The start position is included as a FEN tag unless it's the
+    /// initial position
+    pub fn pgn(&self) -> String {
+        let mut pgn = String::new();
+        let fen = self.start.fen();
+        if fen != Position::START_FEN {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{fen}\"]\n"));
+        }
+        pgn.push_str(&format!("[Result \"{}\"]\n\n", self.result));
+
+        let state = self.start.actual_state();
+        let mut number = state.fm;
+        let mut turn = state.turn;
+        for (i, (_, san)) in self.moves.iter().enumerate() {
+            if turn == Color::WHITE {
+                pgn.push_str(&format!("{number}. "));
+            } else {
+                if i == 0 {
+                    pgn.push_str(&format!("{number}... "));
+                }
+                number += 1;
+            }
+            pgn.push_str(san);
+            pgn.push(' ');
+            turn = 1 - turn;
+        }
+        pgn.push_str(&format!("{}", self.result));
+        pgn
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
+    }
+}
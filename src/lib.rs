@@ -6,7 +6,12 @@
 //! * PEXT bitboards (emulated)
 //! * Make and Undo Position
 //! * Zobrist hashing
+//! * Polyglot opening books
 //! * FEN support
+//! * `no_std` (with `alloc`) by disabling the default `std` feature
+//! * WebAssembly bindings with the `wasm` feature
+//! * C bindings with the `capi` feature
+//! * Python bindings with the `python` feature
 //!
 //! To get started, create a new [Position] and now you can work with legal moves
 //!
@@ -24,18 +29,47 @@
 //! ```
 //!
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod book;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod game;
 mod lookup;
-mod testing;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod testing;
 pub mod types;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, str::FromStr};
 
-use std::{fmt, str::FromStr};
+#[cfg(feature = "std")]
+pub use book::PolyglotBook;
+pub use lookup::{
+    between, bishop_attacks, front_span, front_span_wide, king_attacks, knight_attacks, line,
+    pawn_attacks, queen_attacks, rook_attacks,
+};
 
 use lookup::{
-    between, d12_moves, hv_moves, line, oo_blockers, ooo_blockers, ooo_danger, D12_MASKS,
-    D12_MASKS_2, HV_MASKS, HV_MASKS_2, KING_MASK, KNIGHT_MASK, PAWN_ATTACKS, ZOBRIST_CASTLE,
-    ZOBRIST_EP, ZOBRIST_PIECES,
+    d12_moves, hv_moves, oo_blockers, ooo_blockers, ooo_danger, splitmix64, D12_MASKS, D12_MASKS_2,
+    HV_MASKS, HV_MASKS_2, KING_MASK, KNIGHT_MASK, PAWN_ATTACKS, POLYGLOT_RANDOM, ZOBRIST_CASTLE,
+    ZOBRIST_EP, ZOBRIST_PIECES, ZOBRIST_SIDE,
+};
+use types::{
+    BitBoard, Color, Endgame, Move, MoveDeltas, MoveError, MoveList, Piece, PieceType, Side, Square,
 };
-use types::{BitBoard, Color, Move, MoveList, Piece, Square};
 
 use crate::types::{BitHelpers, Direction};
 
@@ -84,13 +118,95 @@ impl State {
     }
 }
 
+/// Represents the random numbers used for zobrist hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZobristKeys {
+    pub pieces: [[[u64; 64]; 6]; 2],
+    pub castle: [[u64; 4]; 2],
+    pub ep: [[u64; 64]; 2],
+    pub side: u64,
+}
+
+impl ZobristKeys {
+    /// The keys baked into the library, used by default
+    pub const DEFAULT: Self = Self {
+        pieces: ZOBRIST_PIECES,
+        castle: ZOBRIST_CASTLE,
+        ep: ZOBRIST_EP,
+        side: ZOBRIST_SIDE,
+    };
+    /// Generates all the keys from a seed using splitmix64, so the same seed always gives the same
+    /// keys. As it's a const fn, it can be used to build a static:
+    ///
+    /// ```
+    /// use turbochess::ZobristKeys;
+    ///
+    /// static KEYS: ZobristKeys = ZobristKeys::from_seed(42);
+    /// ```
+    pub const fn from_seed(seed: u64) -> Self {
+        let mut keys = Self {
+            pieces: [[[0; 64]; 6]; 2],
+            castle: [[0; 4]; 2],
+            ep: [[0; 64]; 2],
+            side: 0,
+        };
+        let mut state = seed;
+        let mut c = 0;
+        while c < 2 {
+            let mut p = 0;
+            while p < 6 {
+                let mut s = 0;
+                while s < 64 {
+                    (keys.pieces[c][p][s], state) = splitmix64(state);
+                    s += 1;
+                }
+                p += 1;
+            }
+            let mut i = 0;
+            while i < 4 {
+                (keys.castle[c][i], state) = splitmix64(state);
+                i += 1;
+            }
+            let mut s = 0;
+            while s < 64 {
+                (keys.ep[c][s], state) = splitmix64(state);
+                s += 1;
+            }
+            c += 1;
+        }
+        (keys.side, _) = splitmix64(state);
+        keys
+    }
+}
+
+static DEFAULT_KEYS: ZobristKeys = ZobristKeys::DEFAULT;
+
+/// Options for [Position::legal_with]. The default generates the same moves as
+/// [Position::legal]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GenOptions {
+    /// Only generate the promotions to a queen, leaving out the underpromotions
+    pub queen_promotions_only: bool,
+}
+
 /// Represents a position
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Position {
     ply: usize,
     pieces_bb: [[u64; 6]; 2],
-    history: [State; 216],
+    // One state per ply, the last one being the current state
+    history: Vec<State>,
+    // Full hash of the position at every previous ply, saved when a move is made from it and used
+    // to find repetitions
+    key_history: Vec<u64>,
+    // Moves made to reach the current state, null moves included, so they can be undone in order
+    moves: Vec<Move>,
+    // Moves taken back with undo, the next one to redo being the last
+    redo_moves: Vec<Move>,
     hash: u64,
+    // Material of white minus material of black, updated along with the pieces
+    material: i32,
+    keys: &'static ZobristKeys,
     pin_hv: u64,
     pin_d12: u64,
     danger: u64,
@@ -98,32 +214,119 @@ pub struct Position {
 }
 
 impl Position {
+    /// FEN of the initial position
+    pub const START_FEN: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
     /// Creates a new position
     pub fn new() -> Self {
         Self {
             ply: 0,
             pieces_bb: [[0; 6]; 2],
-            history: [State::new(); 216],
+            history: vec![State::new()],
+            key_history: Vec::new(),
+            moves: Vec::new(),
+            redo_moves: Vec::new(),
             hash: 0,
+            material: 0,
+            keys: &DEFAULT_KEYS,
             pin_hv: 0,
             pin_d12: 0,
             danger: 0,
             checkmask: 0,
         }
     }
+    /// Creates a position without pieces, with white to move and no castling rights. Place the
+    /// pieces with [Position::set_piece] and then call [Position::update_checks] (both kings must
+    /// be on the board) before generating moves
+    ///
+    /// ```
+    /// use turbochess::{types::{Color, Piece, Square}, Position};
+    ///
+    /// let mut pos = Position::empty();
+    /// pos.set_piece(Square::E1, Piece::KING, Color::WHITE);
+    /// pos.set_piece(Square::E8, Piece::KING, Color::BLACK);
+    /// pos.set_piece(Square::A1, Piece::ROOK, Color::WHITE);
+    /// pos.update_checks();
+    /// assert_eq!(pos.fen(), "4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+    /// ```
+    pub fn empty() -> Self {
+        let mut pos = Self::new();
+        pos.history[pos.ply].fm = 1;
+        pos
+    }
+    /// Adds a piece to an empty square, updating the hash
+    #[inline(always)]
+    pub fn add_piece(&mut self, square: usize, piece: usize, color: usize) {
+        debug_assert!(
+            self.occupancy() & (1u64 << square) == 0,
+            "Square {} is already occupied",
+            Square::to_string(square)
+        );
+        self.pieces_bb[color][piece] |= 1u64 << square;
+        self.hash ^= self.keys.pieces[color][piece][square];
+        self.material += Self::signed_value(piece, color);
+    }
+    /// Removes the piece on a square (if there is any), updating the hash
+    #[inline(always)]
+    pub fn remove_piece(&mut self, square: usize) {
+        for c in [0, 1] {
+            for p in 0..6 {
+                if self.pieces_bb[c][p] & (1u64 << square) != 0 {
+                    self.pieces_bb[c][p] &= !(1u64 << square);
+                    self.hash ^= self.keys.pieces[c][p][square];
+                    self.material -= Self::signed_value(p, c);
+                    return;
+                }
+            }
+        }
+    }
+    // Value of a piece from white's point of view
+    #[inline(always)]
+    fn signed_value(piece: usize, color: usize) -> i32 {
+        if color == Color::WHITE {
+            Piece::VALUES[piece]
+        } else {
+            -Piece::VALUES[piece]
+        }
+    }
+    /// Gets the material of white minus the material of black, in centipawns. It's kept up to
+    /// date as the pieces change, so it costs nothing to read
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// let pos = Position::from_str("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+    /// assert_eq!(pos.material_balance(), 500);
+    /// ```
+    pub fn material_balance(&self) -> i32 {
+        self.material
+    }
+    /// Computes the material of white minus the material of black from scratch. It's the same as
+    /// [Position::material_balance], which is faster
+    pub fn material(&self) -> i32 {
+        (0..6)
+            .map(|p| {
+                let count = |c: usize| self.pieces_bb[c][p].count_ones() as i32;
+                (count(Color::WHITE) - count(Color::BLACK)) * Piece::VALUES[p]
+            })
+            .sum()
+    }
+    /// Puts a piece on a square, replacing the piece that was there
+    pub fn set_piece(&mut self, square: usize, piece: usize, color: usize) {
+        self.remove_piece(square);
+        self.add_piece(square, piece, color);
+    }
     /// Moves a piece from a square to another.
     pub fn move_quiet(&mut self, from: usize, to: usize) {
         let c = self.color_on(from).unwrap();
         let p = self.piece_on(from).unwrap();
-        self.pieces_bb[c][p] ^= (1u64 << from) | (1u64 << to);
-        self.hash ^= ZOBRIST_PIECES[c][p][from];
-        self.hash ^= ZOBRIST_PIECES[c][p][to];
+        self.remove_piece(from);
+        self.add_piece(to, p, c);
     }
     /// Sets a square. NOTE: It replaces the piece on the square
     pub fn set_square(&mut self, square: usize, piece: usize, color: usize) {
-        self.unset_square(square);
-        self.pieces_bb[color][piece] |= 1u64 << square;
-        self.hash ^= ZOBRIST_PIECES[color][piece][square];
+        self.set_piece(square, piece, color);
     }
     /// Updates the checkmask and pinned mask
     pub fn update_checks(&mut self) {
@@ -135,15 +338,10 @@ impl Position {
     }
     /// Unsets a square
     pub fn unset_square(&mut self, square: usize) {
-        for c in [0, 1] {
-            for p in 0..6 {
-                self.pieces_bb[c][p] &= !(1u64 << square);
-                self.hash ^= ZOBRIST_PIECES[c][p][square];
-            }
-        }
+        self.remove_piece(square);
     }
     /// Gets the color of a piece in a specific square
-    pub fn color_on(self, square: usize) -> Option<usize> {
+    pub fn color_on(&self, square: usize) -> Option<usize> {
         for c in [0, 1] {
             for p in 0..6 {
                 if self.pieces_bb[c][p] & (1u64 << square) != 0 {
@@ -154,7 +352,7 @@ impl Position {
         None
     }
     /// Gets the type of a piece in a specific square
-    pub fn piece_on(self, square: usize) -> Option<usize> {
+    pub fn piece_on(&self, square: usize) -> Option<usize> {
         for c in [0, 1] {
             for p in 0..6 {
                 if self.pieces_bb[c][p] & (1u64 << square) != 0 {
@@ -164,38 +362,765 @@ impl Position {
         }
         None
     }
+    /// Gets the type and color of the piece on a square, as enums
+    ///
+    /// ```
+    /// use turbochess::{types::{PieceType, Side, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// assert_eq!(pos.piece_at(Square::D8), Some((PieceType::Queen, Side::Black)));
+    /// assert_eq!(pos.piece_at(Square::D4), None);
+    /// ```
+    pub fn piece_at(&self, square: usize) -> Option<(PieceType, Side)> {
+        for c in [0, 1] {
+            for p in 0..6 {
+                if self.pieces_bb[c][p] & (1u64 << square) != 0 {
+                    return Some((PieceType::from_index(p), Side::from_index(c)));
+                }
+            }
+        }
+        None
+    }
+    /// Gets the piece and color on every square (a1 = 0, h8 = 63)
+    pub fn to_array(&self) -> [Option<(usize, usize)>; 64] {
+        let mut array = [None; 64];
+        for c in [0, 1] {
+            for p in 0..6 {
+                for s in BitBoard::squares(self.pieces_bb[c][p]) {
+                    array[s] = Some((p, c));
+                }
+            }
+        }
+        array
+    }
+    /// Creates a position from the piece and color on every square (a1 = 0, h8 = 63), like the
+    /// ones given by [Position::to_array]. Both kings must be on the board
+    ///
+    /// ```
+    /// use turbochess::{types::Color, Position, State};
+    ///
+    /// let pos = Position::default();
+    /// let copy = Position::from_array(pos.to_array(), Color::WHITE, State::ALL_CASTLING, None);
+    /// assert_eq!(copy.fen(), pos.fen());
+    /// ```
+    pub fn from_array(
+        array: [Option<(usize, usize)>; 64],
+        turn: usize,
+        castling: u8,
+        ep: Option<usize>,
+    ) -> Self {
+        let mut pos = Self::empty();
+        for (s, square) in array.into_iter().enumerate() {
+            if let Some((p, c)) = square {
+                pos.add_piece(s, p, c);
+            }
+        }
+        if turn == Color::BLACK {
+            pos.hash ^= pos.keys.side;
+        }
+        let state = &mut pos.history[pos.ply];
+        state.turn = turn;
+        state.castling = castling;
+        state.ep = ep;
+        pos.update_checks();
+        pos
+    }
+    /// Mirrors the board vertically and swaps the colors of the pieces, the side to move, the
+    /// castling rights and the en passant square. The result is the same position seen from the
+    /// other side, so it has the same number of moves
+    pub fn flip_colors(&self) -> Self {
+        let array = self.to_array();
+        let mut flipped = [None; 64];
+        for (s, square) in array.into_iter().enumerate() {
+            flipped[s ^ 56] = square.map(|(p, c)| (p, 1 - c));
+        }
+        let state = self.actual_state();
+        let castling = (state.castling & State::WHITE_CASTLING) << 2
+            | (state.castling & State::BLACK_CASTLING) >> 2;
+        let mut pos = Self::from_array(
+            flipped,
+            1 - state.turn,
+            castling,
+            state.ep.map(|ep| ep ^ 56),
+        );
+        pos.history[pos.ply].hm = state.hm;
+        pos.history[pos.ply].fm = state.fm;
+        pos
+    }
+    /// Rotates the board 180 degrees, moving the piece on every square `s` to `63 - s` without
+    /// changing its color. Castling rights and the en passant square are dropped, as the kings,
+    /// rooks and pawns aren't where they would need to be anymore
+    pub fn rotate180(&self) -> Self {
+        let mut array = self.to_array();
+        array.reverse();
+        let state = self.actual_state();
+        let mut pos = Self::from_array(array, state.turn, 0, None);
+        pos.history[pos.ply].hm = state.hm;
+        pos.history[pos.ply].fm = state.fm;
+        pos
+    }
     /// Gets the zobrist hashing of the actual position
-    pub fn hash(self, enpassant: bool) -> u64 {
-        let piece_hash = self.hash;
+    pub fn hash(&self, enpassant: bool) -> u64 {
         let state = self.actual_state();
-        let ep_hash = if let Some(ep) = state.ep {
-            if enpassant {
-                ZOBRIST_EP[state.turn][ep]
-            } else {
-                0
+        let ep = if enpassant { state.ep } else { None };
+        self.hash ^ self.state_hash(state.turn, state.castling, ep)
+    }
+    /// Gets the zobrist keys of the castling rights and the en passant square
+    fn state_hash(&self, turn: usize, castling: u8, ep: Option<usize>) -> u64 {
+        let mut hash = 0;
+        if let Some(ep) = ep {
+            hash ^= self.keys.ep[turn][ep];
+        }
+        for (i, side) in [
+            State::WHITE_00,
+            State::WHITE_000,
+            State::BLACK_00,
+            State::BLACK_000,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if castling & side != 0 {
+                hash ^= self.keys.castle[turn][i];
             }
+        }
+        hash
+    }
+    /// Gets the castling rights that are left after a move from a square to another. Moving the
+    /// king or a rook, or capturing a rook, removes the rights it was giving
+    fn castling_after(castling: u8, from: usize, to: usize) -> u8 {
+        let lost = |sq| match sq {
+            Square::E1 => State::WHITE_CASTLING,
+            Square::H1 => State::WHITE_00,
+            Square::A1 => State::WHITE_000,
+            Square::E8 => State::BLACK_CASTLING,
+            Square::H8 => State::BLACK_00,
+            Square::A8 => State::BLACK_000,
+            _ => 0,
+        };
+        castling & !lost(from) & !lost(to)
+    }
+    /// Computes the value [Position::hash] (with en passant) would have after making a legal
+    /// move, without making it
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// let mv = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    /// let hash = pos.hash_after(mv);
+    /// pos.make_move(mv);
+    /// assert_eq!(pos.hash(true), hash);
+    /// ```
+    pub fn hash_after(&self, mv: Move) -> u64 {
+        let state = self.actual_state();
+        let us = state.turn;
+        let them = 1 - us;
+        let piece = self.piece_on(mv.from()).unwrap();
+        let mut hash = self.hash ^ self.keys.side;
+        hash ^= self.keys.pieces[us][piece][mv.from()];
+        hash ^= self.keys.pieces[us][mv.promotion_piece().unwrap_or(piece)][mv.to()];
+        if mv.is_en_passant() {
+            let sq = (mv.to() as i32 + Direction::relative(Direction::South, us) as i32) as usize;
+            hash ^= self.keys.pieces[them][Piece::PAWN][sq];
+        } else if mv.is_capture() {
+            hash ^= self.keys.pieces[them][self.piece_on(mv.to()).unwrap()][mv.to()];
+        }
+        match mv.flag() {
+            Move::CASTLE_00 => {
+                hash ^= self.keys.pieces[us][Piece::ROOK][State::SHORT_ROOK[us]];
+                hash ^= self.keys.pieces[us][Piece::ROOK][State::SHORT_ROOK[us] - 2];
+            }
+            Move::CASTLE_000 => {
+                hash ^= self.keys.pieces[us][Piece::ROOK][State::LONG_ROOK[us]];
+                hash ^= self.keys.pieces[us][Piece::ROOK][State::LONG_ROOK[us] + 3];
+            }
+            _ => {}
+        }
+        let ep = if mv.flag() == Move::DOUBLE_PUSH {
+            Some((mv.from() as i32 + Direction::relative(Direction::North, us) as i32) as usize)
         } else {
-            0
+            None
         };
-        let mut castle_hash = 0;
-        if state.can_castle(State::WHITE_00) {
-            castle_hash ^= ZOBRIST_CASTLE[state.turn][0];
+        let castling = Self::castling_after(state.castling, mv.from(), mv.to());
+        hash ^ self.state_hash(them, castling, ep)
+    }
+    /// Counts how many times the current position has been on the board since the last capture
+    /// or pawn move, including this time. It's 3 when the position has been repeated three times
+    pub fn repetition_count(&self) -> usize {
+        let key = self.repetition_key();
+        let state = self.actual_state();
+        let first = self.ply.saturating_sub(state.hm);
+        1 + (first..self.ply)
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .filter(|&i| self.key_history[i] == key)
+            .count()
+    }
+    /// Checks if the current position has already been on the board since the last capture or
+    /// pawn move
+    pub fn is_repetition(&self) -> bool {
+        self.repetition_count() > 1
+    }
+    /// Counts how many times the current position has been on the board in the whole history,
+    /// including this time. Unlike [Position::repetition_count], which a draw claim is based on,
+    /// it doesn't stop at the last capture or pawn move given by the halfmove clock, so it's
+    /// meant for cycle detection in a search rather than for the threefold repetition rule
+    pub fn repetition_count_full(&self) -> usize {
+        let key = self.repetition_key();
+        1 + (0..self.ply)
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .filter(|&i| self.key_history[i] == key)
+            .count()
+    }
+    /// Checks if the current position has already been on the board at any point of the history.
+    /// See [Position::repetition_count_full] for how it differs from [Position::is_repetition]
+    pub fn has_cycle(&self) -> bool {
+        self.repetition_count_full() > 1
+    }
+    /// Checks if the position is dead: no sequence of legal moves can lead to a checkmate, so
+    /// under the FIDE rules the game is drawn right away. That's the case with only the kings,
+    /// a king and a single minor piece against a bare king, and any number of bishops that are all
+    /// on squares of the same color. It doesn't cover positions where the side with the material
+    /// merely can't force a mate, like two knights against a bare king, which aren't dead
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// assert!(Position::from_str("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1").unwrap().is_dead());
+    /// assert!(!Position::from_str("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1").unwrap().is_dead());
+    /// ```
+    pub fn is_dead(&self) -> bool {
+        let heavy = |c: usize| {
+            self.pieces_bb[c][Piece::PAWN]
+                | self.pieces_bb[c][Piece::ROOK]
+                | self.pieces_bb[c][Piece::QUEEN]
+        };
+        if heavy(Color::WHITE) | heavy(Color::BLACK) != 0 {
+            return false;
         }
-        if state.can_castle(State::WHITE_000) {
-            castle_hash ^= ZOBRIST_CASTLE[state.turn][1];
+        let knights = self.pieces_bb[Color::WHITE][Piece::KNIGHT]
+            | self.pieces_bb[Color::BLACK][Piece::KNIGHT];
+        let bishops = self.pieces_bb[Color::WHITE][Piece::BISHOP]
+            | self.pieces_bb[Color::BLACK][Piece::BISHOP];
+        let minors = knights | bishops;
+        minors.count_ones() <= 1
+            || knights == 0
+                && (bishops & BitBoard::LIGHT_SQUARES == 0 || bishops & BitBoard::DARK_SQUARES == 0)
+    }
+    /// Classifies the position by the material of both colors, with the stronger side first
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::Endgame, Position};
+    ///
+    /// let pos = Position::from_str("8/8/4k3/8/8/8/8/4K1NB w - - 0 1").unwrap();
+    /// assert_eq!(pos.endgame_type(), Endgame::KBNvK);
+    /// let pos = Position::from_str("8/8/4k3/8/8/8/1r6/4K1N1 w - - 0 1").unwrap();
+    /// assert_eq!(pos.endgame_type(), Endgame::KRvKN);
+    /// ```
+    pub fn endgame_type(&self) -> Endgame {
+        // Counts of pawns, knights, bishops, rooks and queens
+        let counts = |c: usize| -> [u32; 5] {
+            let bb = &self.pieces_bb[c];
+            [
+                bb[Piece::PAWN].count_ones(),
+                bb[Piece::KNIGHT].count_ones(),
+                bb[Piece::BISHOP].count_ones(),
+                bb[Piece::ROOK].count_ones(),
+                bb[Piece::QUEEN].count_ones(),
+            ]
+        };
+        let (white, black) = (counts(Color::WHITE), counts(Color::BLACK));
+        // The stronger side has the heavier pieces, compared from the queens down
+        let (strong, weak) = if white.iter().rev().cmp(black.iter().rev()).is_ge() {
+            (white, black)
+        } else {
+            (black, white)
+        };
+        match (strong, weak) {
+            ([0, 0, 0, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KvK,
+            ([1, 0, 0, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KPvK,
+            ([0, 1, 0, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KNvK,
+            ([0, 0, 1, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KBvK,
+            ([0, 2, 0, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KNNvK,
+            ([0, 1, 1, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KBNvK,
+            ([0, 0, 2, 0, 0], [0, 0, 0, 0, 0]) => Endgame::KBBvK,
+            ([0, 0, 0, 1, 0], [0, 0, 0, 0, 0]) => Endgame::KRvK,
+            ([0, 0, 0, 0, 1], [0, 0, 0, 0, 0]) => Endgame::KQvK,
+            ([1, 0, 0, 0, 0], [1, 0, 0, 0, 0]) => Endgame::KPvKP,
+            ([0, 0, 0, 1, 0], [1, 0, 0, 0, 0]) => Endgame::KRvKP,
+            ([0, 0, 0, 0, 1], [1, 0, 0, 0, 0]) => Endgame::KQvKP,
+            ([0, 0, 0, 1, 0], [0, 1, 0, 0, 0]) => Endgame::KRvKN,
+            ([0, 0, 0, 1, 0], [0, 0, 1, 0, 0]) => Endgame::KRvKB,
+            ([0, 0, 0, 1, 0], [0, 0, 0, 1, 0]) => Endgame::KRvKR,
+            ([1, 0, 0, 1, 0], [0, 0, 0, 1, 0]) => Endgame::KRPvKR,
+            ([0, 0, 0, 0, 1], [0, 0, 0, 1, 0]) => Endgame::KQvKR,
+            ([0, 0, 0, 0, 1], [0, 0, 0, 0, 1]) => Endgame::KQvKQ,
+            _ => Endgame::Other,
         }
-        if state.can_castle(State::BLACK_00) {
-            castle_hash ^= ZOBRIST_CASTLE[state.turn][2];
+    }
+    /// Gets a zobrist hash of only the pawns of both colors, useful for caching pawn structure
+    /// evaluations. It's computed on demand from the pawn bitboards
+    pub fn pawn_hash(&self) -> u64 {
+        let mut hash = 0;
+        for c in [0, 1] {
+            let mut bb = self.pieces_bb[c][Piece::PAWN];
+            while bb != 0 {
+                hash ^= self.keys.pieces[c][Piece::PAWN][bb.bit_scan()];
+                bb = bb.pop_lsb();
+            }
         }
-        if state.can_castle(State::BLACK_000) {
-            castle_hash ^= ZOBRIST_CASTLE[state.turn][3];
+        hash
+    }
+    /// Copies the position without its history, so the current state is at ply 0. The moves
+    /// that led to it can't be undone and don't count for repetitions in the copy
+    pub fn fork(&self) -> Self {
+        Self {
+            ply: 0,
+            history: vec![self.actual_state()],
+            key_history: Vec::new(),
+            moves: Vec::new(),
+            redo_moves: Vec::new(),
+            ..*self
         }
-        piece_hash ^ ep_hash ^ castle_hash
+    }
+    /// Loads a FEN into this position, replacing the board and clearing the history, so it can be
+    /// reused without allocating a new one. The zobrist keys are kept. If the FEN is invalid, the
+    /// error is returned and the position is left untouched
+    ///
+    /// ```
+    /// use turbochess::Position;
+    ///
+    /// let mut pos = Position::default();
+    /// let fen = "4k3/8/8/8/8/8/8/R3K3 b - - 3 40";
+    /// pos.set_fen(fen).unwrap();
+    /// assert_eq!(pos.fen(), fen);
+    /// ```
+    pub fn set_fen(&mut self, fen: &str) -> Result<(), String> {
+        let params = fen.split(" ").collect::<Vec<&str>>();
+        if params.len() != 6 {
+            return Err(format!(
+                "Invalid FEN: expected 6 fields, got {}",
+                params.len()
+            ));
+        }
+        let ranks = params[0].split("/").collect::<Vec<&str>>();
+        if ranks.len() != 8 {
+            return Err(format!(
+                "Invalid FEN: expected 8 ranks, got {}",
+                ranks.len()
+            ));
+        }
+
+        // Everything is parsed before the position is touched, so an error leaves it as it was
+        let mut pieces = [[0u64; 6]; 2];
+        for (r, rank) in ranks.iter().rev().enumerate() {
+            let mut file = 0;
+            for piece in rank.chars() {
+                if let Some(n) = piece.to_digit(10) {
+                    file += n as usize;
+                    continue;
+                }
+                if !"pnbrqkPNBRQK".contains(piece) {
+                    return Err(format!("Invalid FEN: unknown piece '{piece}'"));
+                }
+                if file < 8 {
+                    let color = if piece.is_uppercase() { 0 } else { 1 };
+                    pieces[color][Piece::from_char(piece)] |= 1u64 << (8 * r + file);
+                }
+                file += 1;
+            }
+            if file != 8 {
+                return Err(format!("Invalid FEN: rank '{rank}' doesn't have 8 squares"));
+            }
+        }
+        if pieces[Color::WHITE][Piece::KING] == 0 || pieces[Color::BLACK][Piece::KING] == 0 {
+            return Err(String::from("Invalid FEN: one king is missing"));
+        }
+
+        let mut state = State::new();
+        state.turn = match params[1] {
+            "w" => Color::WHITE,
+            "b" => Color::BLACK,
+            _ => return Err(format!("Invalid FEN: unknown side to move '{}'", params[1])),
+        };
+
+        if params[2].contains("K") {
+            state.castling |= State::WHITE_00
+        }
+        if params[2].contains("Q") {
+            state.castling |= State::WHITE_000
+        }
+        if params[2].contains("k") {
+            state.castling |= State::BLACK_00
+        }
+        if params[2].contains("q") {
+            state.castling |= State::BLACK_000
+        }
+
+        if params[3] != "-" {
+            let ep = params[3].as_bytes();
+            if ep.len() != 2 || !(b'a'..=b'h').contains(&ep[0]) || !(b'1'..=b'8').contains(&ep[1]) {
+                return Err(format!(
+                    "Invalid FEN: bad en passant square '{}'",
+                    params[3]
+                ));
+            }
+            state.ep = Some(Square::from_str(params[3]))
+        }
+
+        if params[4] != "-" {
+            state.hm = params[4]
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid FEN: bad halfmove clock '{}'", params[4]))?
+        }
+        if params[5] != "-" {
+            state.fm = params[5]
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid FEN: bad fullmove number '{}'", params[5]))?
+        }
+
+        self.ply = 0;
+        self.pieces_bb = [[0; 6]; 2];
+        self.material = 0;
+        self.history.clear();
+        self.history.push(state);
+        self.key_history.clear();
+        self.moves.clear();
+        self.redo_moves.clear();
+        self.hash = 0;
+        for color in [Color::WHITE, Color::BLACK] {
+            for (piece, &bb) in pieces[color].iter().enumerate() {
+                let mut bb = bb;
+                while bb != 0 {
+                    self.add_piece(bb.bit_scan(), piece, color);
+                    bb = bb.pop_lsb();
+                }
+            }
+        }
+        if state.turn == Color::BLACK {
+            self.hash ^= self.keys.side;
+        }
+
+        let (c, hv, d12) = self.check_and_pin();
+        self.checkmask = c;
+        self.pin_hv = hv;
+        self.pin_d12 = d12;
+        self.danger = self.attacks();
+
+        Ok(())
+    }
+    /// Creates the initial position. It's the same as [Position::default]
+    pub fn startpos() -> Self {
+        Self::default()
+    }
+    /// Sets the initial position in place, clearing the history. The zobrist keys are kept
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// let mut pos = Position::from_str("4k3/8/8/8/8/8/8/R3K3 b - - 3 40").unwrap();
+    /// pos.reset();
+    /// assert_eq!(pos.fen(), Position::START_FEN);
+    /// ```
+    pub fn reset(&mut self) {
+        self.set_fen(Self::START_FEN).unwrap();
+    }
+    /// Uses another set of zobrist keys for hashing this position
+    pub fn with_keys(mut self, keys: &'static ZobristKeys) -> Self {
+        self.keys = keys;
+        self.hash = self.compute_hash();
+        self
+    }
+    /// Computes the incremental part of the hash (pieces and side to move) from scratch
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for c in [0, 1] {
+            for p in 0..6 {
+                let mut bb = self.pieces_bb[c][p];
+                while bb != 0 {
+                    hash ^= self.keys.pieces[c][p][bb.bit_scan()];
+                    bb = bb.pop_lsb();
+                }
+            }
+        }
+        if self.actual_state().turn == Color::BLACK {
+            hash ^= self.keys.side;
+        }
+        hash
+    }
+    /// Computes the full hash of the position from scratch. It's the same as [Position::hash]
+    /// with the en passant square, which is updated incrementally instead
+    pub fn recompute_hash(&self) -> u64 {
+        let state = self.actual_state();
+        self.compute_hash() ^ self.state_hash(state.turn, state.castling, state.ep)
+    }
+    /// Computes the key of the position in the Polyglot opening book format. It uses the fixed
+    /// random numbers of that format instead of the [ZobristKeys] of the position, and the en
+    /// passant square only counts when a pawn of the side to move stands next to the pushed pawn
+    ///
+    /// ```
+    /// use turbochess::Position;
+    ///
+    /// assert_eq!(Position::default().polyglot_key(), 0x463b96181691fc9c);
+    /// ```
+    pub fn polyglot_key(&self) -> u64 {
+        let state = self.actual_state();
+        let mut key = 0;
+        for color in [Color::WHITE, Color::BLACK] {
+            for piece in 0..6 {
+                let kind = 2 * piece + (color == Color::WHITE) as usize;
+                let mut bb = self.pieces_bb[color][piece];
+                while bb != 0 {
+                    key ^= POLYGLOT_RANDOM[64 * kind + bb.bit_scan()];
+                    bb = bb.pop_lsb();
+                }
+            }
+        }
+        let rights = [
+            State::WHITE_00,
+            State::WHITE_000,
+            State::BLACK_00,
+            State::BLACK_000,
+        ];
+        for (i, right) in rights.into_iter().enumerate() {
+            if state.can_castle(right) {
+                key ^= POLYGLOT_RANDOM[768 + i];
+            }
+        }
+        if let Some(ep) = state.ep.filter(|_| self.ep_capturable()) {
+            key ^= POLYGLOT_RANDOM[772 + Square::file(ep)];
+        }
+        if state.turn == Color::WHITE {
+            key ^= POLYGLOT_RANDOM[780];
+        }
+        key
+    }
+    /// Checks if a pawn of the side to move stands beside the pawn that was just pushed two
+    /// squares, so the en passant square can matter
+    fn ep_capturable(&self) -> bool {
+        let state = self.actual_state();
+        let Some(ep) = state.ep else {
+            return false;
+        };
+        // The pawn that was pushed is on the square past the en passant square
+        let pushed = BitBoard::shift_dir(
+            1u64 << ep,
+            Direction::relative(Direction::South, state.turn),
+        );
+        let beside = BitBoard::shift_dir(pushed, Direction::East)
+            | BitBoard::shift_dir(pushed, Direction::West);
+        beside & self.pieces_bb[state.turn][Piece::PAWN] != 0
+    }
+    /// Gets the key that repetitions are detected with: the hash, with the en passant square
+    /// only when a pawn can capture on it, as positions are the same otherwise
+    fn repetition_key(&self) -> u64 {
+        self.hash(self.ep_capturable())
     }
     /// Gets the actual state of the game
     pub fn actual_state(&self) -> State {
         self.history[self.ply]
     }
+    /// Gets the color to move
+    ///
+    /// ```
+    /// use turbochess::{types::Color, Position};
+    ///
+    /// assert_eq!(Position::default().side_to_move(), Color::WHITE);
+    /// ```
+    pub fn side_to_move(&self) -> usize {
+        self.actual_state().turn
+    }
+    /// Checks if a color can still castle kingside
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::Color, Position};
+    ///
+    /// let pos = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+    /// assert!(pos.can_castle_kingside(Color::WHITE));
+    /// assert!(!pos.can_castle_kingside(Color::BLACK));
+    /// ```
+    pub fn can_castle_kingside(&self, color: usize) -> bool {
+        self.actual_state().can_castle(State::SHORT[color])
+    }
+    /// Checks if a color can still castle queenside
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::Color, Position};
+    ///
+    /// let pos = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+    /// assert!(!pos.can_castle_queenside(Color::WHITE));
+    /// assert!(pos.can_castle_queenside(Color::BLACK));
+    /// ```
+    pub fn can_castle_queenside(&self, color: usize) -> bool {
+        self.actual_state().can_castle(State::LONG[color])
+    }
+    /// Gets the en passant square, if there is any
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::Square, Position};
+    ///
+    /// let pos = Position::from_str("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap();
+    /// assert_eq!(pos.en_passant_square(), Some(Square::E3));
+    /// ```
+    pub fn en_passant_square(&self) -> Option<usize> {
+        self.actual_state().ep
+    }
+    /// Gets the number of halfmoves since the last capture or pawn move
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// let pos = Position::from_str("4k3/8/8/8/8/8/8/4K3 w - - 12 40").unwrap();
+    /// assert_eq!(pos.halfmove_clock(), 12);
+    /// ```
+    pub fn halfmove_clock(&self) -> usize {
+        self.actual_state().hm
+    }
+    /// Gets the fullmove number, which starts at 1 and is incremented after black moves
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// let pos = Position::from_str("4k3/8/8/8/8/8/8/4K3 w - - 12 40").unwrap();
+    /// assert_eq!(pos.fullmove_number(), 40);
+    /// ```
+    pub fn fullmove_number(&self) -> usize {
+        self.actual_state().fm
+    }
+    /// Gets the number of moves made since the position was created
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// pos.make_move(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH));
+    /// assert_eq!(pos.ply(), 1);
+    /// ```
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+    /// Sets the castling rights, as a combination of the [State] castling flags. They aren't
+    /// checked against the position of the kings and rooks
+    ///
+    /// ```
+    /// use turbochess::{Position, State};
+    ///
+    /// let mut pos = Position::default();
+    /// pos.set_castling_rights(State::WHITE_00 | State::BLACK_000);
+    /// assert_eq!(pos.fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kq - 0 1");
+    /// ```
+    pub fn set_castling_rights(&mut self, rights: u8) {
+        self.history[self.ply].castling = rights;
+        self.update_checks();
+    }
+    /// Sets or clears the en passant square
+    pub fn set_en_passant(&mut self, square: Option<usize>) {
+        self.history[self.ply].ep = square;
+        self.update_checks();
+    }
+    /// Sets the color to move
+    pub fn set_turn(&mut self, color: usize) {
+        if self.history[self.ply].turn != color {
+            self.history[self.ply].turn = color;
+            self.hash ^= self.keys.side;
+        }
+        self.update_checks();
+    }
+    /// Checks that the position makes sense, as a position built by hand may not: each side has
+    /// one king, no square holds two pieces, no pawn is on the first or last rank, the side not to
+    /// move isn't in check, the castling rights match the kings and rooks, and the en passant
+    /// square is behind a pawn that could have just pushed two squares
+    ///
+    /// ```
+    /// use turbochess::{types::{Color, Piece, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// assert!(pos.is_valid().is_ok());
+    /// pos.set_piece(Square::E4, Piece::KING, Color::WHITE);
+    /// assert!(pos.is_valid().is_err());
+    /// ```
+    pub fn is_valid(&self) -> Result<(), String> {
+        let state = self.actual_state();
+        let names = ["White", "Black"];
+        for c in [Color::WHITE, Color::BLACK] {
+            let kings = self.pieces_bb[c][Piece::KING].count_ones();
+            if kings != 1 {
+                return Err(format!("{} has {kings} kings", names[c]));
+            }
+        }
+        let mut occ = 0u64;
+        for bb in self.pieces_bb.iter().flatten() {
+            if occ & bb != 0 {
+                return Err(format!(
+                    "Two pieces on {}",
+                    Square::to_string((occ & bb).bit_scan())
+                ));
+            }
+            occ |= bb;
+        }
+        let pawns = self.pieces(Piece::PAWN) & (BitBoard::rank(0) | BitBoard::rank(7));
+        if pawns != 0 {
+            return Err(format!(
+                "Pawn on the first or last rank on {}",
+                Square::to_string(pawns.bit_scan())
+            ));
+        }
+        if self.is_in_check(1 - state.turn) {
+            return Err(format!(
+                "{} is in check but it isn't its turn",
+                names[1 - state.turn]
+            ));
+        }
+        for c in [Color::WHITE, Color::BLACK] {
+            for (side, rook) in [
+                (State::SHORT[c], State::SHORT_ROOK[c]),
+                (State::LONG[c], State::LONG_ROOK[c]),
+            ] {
+                if state.can_castle(side)
+                    && (self.king(c) != State::KING_START[c]
+                        || self.pieces_bb[c][Piece::ROOK] & (1u64 << rook) == 0)
+                {
+                    return Err(format!(
+                        "{} can castle without the king and rook on {} and {}",
+                        names[c],
+                        Square::to_string(State::KING_START[c]),
+                        Square::to_string(rook)
+                    ));
+                }
+            }
+        }
+        if let Some(ep) = state.ep {
+            let them = 1 - state.turn;
+            // The pawn went from the square behind the en passant square to the one in front
+            let (start, pawn) = if them == Color::WHITE {
+                (ep.wrapping_sub(8), ep + 8)
+            } else {
+                (ep + 8, ep.wrapping_sub(8))
+            };
+            if BitBoard::relative_rank(3, them) & (1u64 << ep) == 0
+                || self.pieces_bb[them][Piece::PAWN] & (1u64 << pawn) == 0
+                || occ & (1u64 << ep | 1u64 << start) != 0
+            {
+                return Err(format!(
+                    "No pawn could have just pushed past {}",
+                    Square::to_string(ep)
+                ));
+            }
+        }
+        Ok(())
+    }
     /// Gets a bitboard of all the pieces of a specific color and type
 
     #[inline(always)]
@@ -240,43 +1165,78 @@ impl Position {
     pub fn d12_sliders(&self, color: usize) -> u64 {
         self.bb_of(color, Piece::BISHOP) | self.bb_of(color, Piece::QUEEN)
     }
-    /// Makes a move without checking its legability
+    /// Gets the squares a move would change, with the piece and color on each before and after it,
+    /// without making it
+    ///
+    /// ```
+    /// use turbochess::{types::{Color, Move, Piece, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// let deltas = pos.move_deltas(Move::new(Square::G1, Square::F3, Move::QUIET));
+    /// assert_eq!(deltas.count(), 2);
+    /// assert_eq!(deltas.as_slice()[1].after, Some((Piece::KNIGHT, Color::WHITE)));
+    /// ```
+    pub fn move_deltas(&self, mv: Move) -> MoveDeltas {
+        let mut deltas = MoveDeltas::new();
+        let on = |sq| Some((self.piece_on(sq)?, self.color_on(sq)?));
+        let turn = self.actual_state().turn;
+        let moving = on(mv.from());
+        deltas.add(mv.from(), moving, None);
+        match mv.flag() {
+            Move::CASTLE_00 | Move::CASTLE_000 => {
+                let (rook_from, rook_to) = if mv.flag() == Move::CASTLE_00 {
+                    (mv.from() + 3, mv.from() + 1)
+                } else {
+                    (mv.from() - 4, mv.from() - 1)
+                };
+                deltas.add(mv.to(), None, moving);
+                deltas.add(rook_from, on(rook_from), None);
+                deltas.add(rook_to, None, on(rook_from));
+            }
+            Move::EN_PASSANT => {
+                let captured =
+                    (mv.to() as i32 + Direction::relative(Direction::South, turn) as i32) as usize;
+                deltas.add(mv.to(), None, moving);
+                deltas.add(captured, on(captured), None);
+            }
+            _ => {
+                let after = match mv.promotion_piece() {
+                    Some(piece) => Some((piece, turn)),
+                    None => moving,
+                };
+                deltas.add(mv.to(), on(mv.to()), after);
+            }
+        }
+        deltas
+    }
+    /// Makes a move without checking its legability. Returns the type of the captured piece, if any
 
     #[inline(always)]
-    pub fn make_move(&mut self, mv: Move) {
+    pub fn make_move(&mut self, mv: Move) -> Option<usize> {
+        self.key_history.push(self.repetition_key());
+        self.moves.push(mv);
+        self.follow_redo(mv);
         let state = self.actual_state();
         self.ply += 1;
+        self.history.push(State::new());
         self.history[self.ply].turn = 1 - state.turn;
         self.history[self.ply].fm = state.fm;
-        self.history[self.ply].hm = state.hm;
-        self.history[self.ply].castling = state.castling;
+        // Moving the king or a rook, or capturing a rook, removes the rights it was giving
+        self.history[self.ply].castling = Self::castling_after(state.castling, mv.from(), mv.to());
         if state.turn == Color::BLACK {
             self.history[self.ply].fm += 1
         }
-        let mut hm_reset = false;
-        if self.piece_on(mv.from()).unwrap() == Piece::PAWN {
-            hm_reset = true;
-        }
+        // The halfmove clock restarts after every capture and pawn move
+        let hm_reset = match mv.flag() {
+            Move::QUIET => self.piece_on(mv.from()) == Some(Piece::PAWN),
+            Move::CASTLE_00 | Move::CASTLE_000 => false,
+            // Double pushes, promotions, en passant and other captures
+            _ => true,
+        };
+        self.history[self.ply].hm = if hm_reset { 0 } else { state.hm + 1 };
+        self.hash ^= self.keys.side;
         match mv.flag() {
-            Move::QUIET => {
-                // If we move the king, remove all castlings
-                if self.piece_on(mv.from()).unwrap() == Piece::KING {
-                    self.history[self.ply].castling &= !State::CASTLINGS[state.turn]
-                }
-                if self.piece_on(mv.from()).unwrap() == Piece::ROOK {
-                    if state.can_castle(State::SHORT[state.turn])
-                        && mv.from() == State::SHORT_ROOK[state.turn]
-                    {
-                        self.history[self.ply].castling &= !State::SHORT[state.turn];
-                    }
-                    if state.can_castle(State::LONG[state.turn])
-                        && mv.from() == State::LONG_ROOK[state.turn]
-                    {
-                        self.history[self.ply].castling &= !State::LONG[state.turn];
-                    }
-                }
-                self.move_quiet(mv.from(), mv.to())
-            }
+            Move::QUIET => self.move_quiet(mv.from(), mv.to()),
             Move::DOUBLE_PUSH => {
                 self.move_quiet(mv.from(), mv.to());
                 self.history[self.ply].ep = Some(
@@ -288,225 +1248,157 @@ impl Position {
                 if state.turn == Color::WHITE {
                     self.move_quiet(Square::E1, Square::G1);
                     self.move_quiet(Square::H1, Square::F1);
-                    // Remove the castling
-                    self.history[self.ply].castling &= !State::WHITE_CASTLING;
                 } else {
                     self.move_quiet(Square::E8, Square::G8);
                     self.move_quiet(Square::H8, Square::F8);
-                    // Remove the castling
-                    self.history[self.ply].castling &= !State::BLACK_CASTLING;
                 }
             }
             Move::CASTLE_000 => {
                 if state.turn == Color::WHITE {
                     self.move_quiet(Square::E1, Square::C1);
                     self.move_quiet(Square::A1, Square::D1);
-                    // Remove the castling
-                    self.history[self.ply].castling &= !State::WHITE_CASTLING;
                 } else {
                     self.move_quiet(Square::E8, Square::C8);
                     self.move_quiet(Square::A8, Square::D8);
-                    // Remove the castling
-                    self.history[self.ply].castling &= !State::BLACK_CASTLING;
                 }
             }
             Move::EN_PASSANT => {
+                self.history[self.ply].captured = Some(Piece::PAWN);
                 self.move_quiet(mv.from(), mv.to());
-                self.unset_square(
+                self.remove_piece(
                     (mv.to() as i32 + Direction::relative(Direction::South, state.turn) as i32)
                         as usize,
                 )
             }
             Move::PR_N => {
-                self.unset_square(mv.from());
-                self.set_square(mv.to(), Piece::KNIGHT, state.turn);
+                self.remove_piece(mv.from());
+                self.add_piece(mv.to(), Piece::KNIGHT, state.turn);
             }
             Move::PR_B => {
-                self.unset_square(mv.from());
-                self.set_square(mv.to(), Piece::BISHOP, state.turn);
+                self.remove_piece(mv.from());
+                self.add_piece(mv.to(), Piece::BISHOP, state.turn);
             }
             Move::PR_R => {
-                self.unset_square(mv.from());
-                self.set_square(mv.to(), Piece::ROOK, state.turn);
+                self.remove_piece(mv.from());
+                self.add_piece(mv.to(), Piece::ROOK, state.turn);
             }
             Move::PR_Q => {
-                self.unset_square(mv.from());
-                self.set_square(mv.to(), Piece::QUEEN, state.turn);
+                self.remove_piece(mv.from());
+                self.add_piece(mv.to(), Piece::QUEEN, state.turn);
             }
             Move::PC_N => {
-                hm_reset = true;
-                self.unset_square(mv.from());
+                self.remove_piece(mv.from());
                 self.history[self.ply].captured = Some(self.piece_on(mv.to()).unwrap());
-                self.set_square(mv.to(), Piece::KNIGHT, state.turn);
-                // If captures a rook that can caslte, then remove that castle
-                if state.can_castle(State::WHITE_00) {
-                    if mv.to() == Square::H1 {
-                        self.history[self.ply].castling &= !State::WHITE_00
-                    }
-                }
-                if state.can_castle(State::WHITE_000) {
-                    if mv.to() == Square::A1 {
-                        self.history[self.ply].castling &= !State::WHITE_000
-                    }
-                }
-                if state.can_castle(State::BLACK_00) {
-                    if mv.to() == Square::H8 {
-                        self.history[self.ply].castling &= !State::BLACK_00
-                    }
-                }
-                if state.can_castle(State::BLACK_000) {
-                    if mv.to() == Square::A8 {
-                        self.history[self.ply].castling &= !State::BLACK_000
-                    }
-                }
+                self.remove_piece(mv.to());
+                self.add_piece(mv.to(), Piece::KNIGHT, state.turn);
             }
             Move::PC_B => {
-                hm_reset = true;
-                self.unset_square(mv.from());
+                self.remove_piece(mv.from());
                 self.history[self.ply].captured = Some(self.piece_on(mv.to()).unwrap());
-                self.unset_square(mv.to());
-                self.set_square(mv.to(), Piece::BISHOP, state.turn);
-                // If captures a rook that can caslte, then remove that castle
-                if state.can_castle(State::WHITE_00) {
-                    if mv.to() == Square::H1 {
-                        self.history[self.ply].castling &= !State::WHITE_00
-                    }
-                }
-                if state.can_castle(State::WHITE_000) {
-                    if mv.to() == Square::A1 {
-                        self.history[self.ply].castling &= !State::WHITE_000
-                    }
-                }
-                if state.can_castle(State::BLACK_00) {
-                    if mv.to() == Square::H8 {
-                        self.history[self.ply].castling &= !State::BLACK_00
-                    }
-                }
-                if state.can_castle(State::BLACK_000) {
-                    if mv.to() == Square::A8 {
-                        self.history[self.ply].castling &= !State::BLACK_000
-                    }
-                }
+                self.remove_piece(mv.to());
+                self.add_piece(mv.to(), Piece::BISHOP, state.turn);
             }
             Move::PC_R => {
-                hm_reset = true;
-                self.unset_square(mv.from());
+                self.remove_piece(mv.from());
                 self.history[self.ply].captured = Some(self.piece_on(mv.to()).unwrap());
-                self.unset_square(mv.to());
-                self.set_square(mv.to(), Piece::ROOK, state.turn);
-                // If captures a rook that can caslte, then remove that castle
-                if state.can_castle(State::WHITE_00) {
-                    if mv.to() == Square::H1 {
-                        self.history[self.ply].castling &= !State::WHITE_00
-                    }
-                }
-                if state.can_castle(State::WHITE_000) {
-                    if mv.to() == Square::A1 {
-                        self.history[self.ply].castling &= !State::WHITE_000
-                    }
-                }
-                if state.can_castle(State::BLACK_00) {
-                    if mv.to() == Square::H8 {
-                        self.history[self.ply].castling &= !State::BLACK_00
-                    }
-                }
-                if state.can_castle(State::BLACK_000) {
-                    if mv.to() == Square::A8 {
-                        self.history[self.ply].castling &= !State::BLACK_000
-                    }
-                }
+                self.remove_piece(mv.to());
+                self.add_piece(mv.to(), Piece::ROOK, state.turn);
             }
             Move::PC_Q => {
-                hm_reset = true;
-                self.unset_square(mv.from());
+                self.remove_piece(mv.from());
                 self.history[self.ply].captured = Some(self.piece_on(mv.to()).unwrap());
-                self.unset_square(mv.to());
-                self.set_square(mv.to(), Piece::QUEEN, state.turn);
-                // If captures a rook that can caslte, then remove that castle
-                if state.can_castle(State::WHITE_00) {
-                    if mv.to() == Square::H1 {
-                        self.history[self.ply].castling &= !State::WHITE_00
-                    }
-                }
-                if state.can_castle(State::WHITE_000) {
-                    if mv.to() == Square::A1 {
-                        self.history[self.ply].castling &= !State::WHITE_000
-                    }
-                }
-                if state.can_castle(State::BLACK_00) {
-                    if mv.to() == Square::H8 {
-                        self.history[self.ply].castling &= !State::BLACK_00
-                    }
-                }
-                if state.can_castle(State::BLACK_000) {
-                    if mv.to() == Square::A8 {
-                        self.history[self.ply].castling &= !State::BLACK_000
-                    }
-                }
+                self.remove_piece(mv.to());
+                self.add_piece(mv.to(), Piece::QUEEN, state.turn);
             }
             Move::CAPTURE => {
-                hm_reset = true;
-                // If we move the king, remove all castlings
-                if self.piece_on(mv.from()).unwrap() == Piece::KING {
-                    self.history[self.ply].castling &= !State::CASTLINGS[state.turn]
-                } else {
-                    // If captures a rook that can caslte, then remove that castle
-                    if state.can_castle(State::WHITE_00) {
-                        if mv.to() == Square::H1 {
-                            self.history[self.ply].castling &= !State::WHITE_00
-                        }
-                    }
-                    if state.can_castle(State::WHITE_000) {
-                        if mv.to() == Square::A1 {
-                            self.history[self.ply].castling &= !State::WHITE_000
-                        }
-                    }
-                    if state.can_castle(State::BLACK_00) {
-                        if mv.to() == Square::H8 {
-                            self.history[self.ply].castling &= !State::BLACK_00
-                        }
-                    }
-                    if state.can_castle(State::BLACK_000) {
-                        if mv.to() == Square::A8 {
-                            self.history[self.ply].castling &= !State::BLACK_000
-                        }
-                    }
-                }
-                if self.piece_on(mv.from()).unwrap() == Piece::ROOK {
-                    if state.can_castle(State::SHORT[state.turn])
-                        && mv.from() == State::SHORT_ROOK[state.turn]
-                    {
-                        self.history[self.ply].castling &= !State::SHORT[state.turn];
-                    }
-                    if state.can_castle(State::LONG[state.turn])
-                        && mv.from() == State::LONG_ROOK[state.turn]
-                    {
-                        self.history[self.ply].castling &= !State::LONG[state.turn];
-                    }
-                }
                 self.history[self.ply].captured = Some(self.piece_on(mv.to()).unwrap());
-                self.unset_square(mv.to());
+                self.remove_piece(mv.to());
                 self.move_quiet(mv.from(), mv.to());
             }
             _ => {
                 unreachable!("Invalid move flag")
             }
         }
-        if hm_reset {
-            self.history[self.ply].hm = 0
+        self.update_checks();
+        self.history[self.ply].captured
+    }
+    /// Builds a move from its start and destination squares, inferring the flag from the board
+    /// (captures, double pushes, en passant, castling and promotions). Returns `None` if the move
+    /// isn't legal or a promotion piece is missing
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// let mv = pos.move_from_squares(Square::E2, Square::E4, None);
+    /// assert_eq!(mv, Some(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)));
+    /// ```
+    pub fn move_from_squares(&self, from: usize, to: usize, promo: Option<usize>) -> Option<Move> {
+        let piece = self.piece_on(from)?;
+        let state = self.actual_state();
+        let capture = self.colors(1 - state.turn) & (1u64 << to) != 0;
+        let flag = if piece == Piece::KING && from.abs_diff(to) == 2 {
+            if to > from {
+                Move::CASTLE_00
+            } else {
+                Move::CASTLE_000
+            }
+        } else if piece == Piece::PAWN && (to / 8 == 0 || to / 8 == 7) {
+            match (promo?, capture) {
+                (Piece::KNIGHT, false) => Move::PR_N,
+                (Piece::BISHOP, false) => Move::PR_B,
+                (Piece::ROOK, false) => Move::PR_R,
+                (Piece::QUEEN, false) => Move::PR_Q,
+                (Piece::KNIGHT, true) => Move::PC_N,
+                (Piece::BISHOP, true) => Move::PC_B,
+                (Piece::ROOK, true) => Move::PC_R,
+                (Piece::QUEEN, true) => Move::PC_Q,
+                _ => return None,
+            }
+        } else if piece == Piece::PAWN && state.ep == Some(to) && from % 8 != to % 8 {
+            Move::EN_PASSANT
+        } else if piece == Piece::PAWN && from.abs_diff(to) == 16 {
+            Move::DOUBLE_PUSH
+        } else if capture {
+            Move::CAPTURE
         } else {
-            self.history[self.ply].hm += 1
+            Move::QUIET
+        };
+        let mv = Move::new(from, to, flag);
+        self.legal().into_iter().find(|&m| m == mv)
+    }
+    /// Makes a move after checking that it's legal. If it isn't, the position is left untouched
+    pub fn try_make_move(&mut self, mv: Move) -> Result<(), MoveError> {
+        self.check_move(mv)?;
+        self.make_move(mv);
+        Ok(())
+    }
+    /// Checks that a move is legal, with the reason if it isn't
+    pub(crate) fn check_move(&self, mv: Move) -> Result<(), MoveError> {
+        match self.color_on(mv.from()) {
+            None => Err(MoveError::EmptySquare),
+            Some(c) if c != self.actual_state().turn => Err(MoveError::NotYourPiece),
+            _ if !self.legal().contains(mv) => Err(MoveError::Illegal),
+            _ => Ok(()),
         }
-        self.update_checks();
     }
-    /// Undoes a move
+    /// Undoes a move, which must be the last one made. [Position::undo] does the same without
+    /// passing the move back in
 
     #[inline(always)]
     pub fn undo_move(&mut self, mv: Move) {
+        debug_assert_eq!(
+            self.moves.last(),
+            Some(&mv),
+            "Undoing a move that isn't the last one made"
+        );
         // Replace the actual state
         let state = self.actual_state();
-        self.history[self.ply] = State::new();
+        self.history.pop();
+        self.key_history.pop();
+        self.moves.pop();
         self.ply -= 1;
+        self.hash ^= self.keys.side;
         match mv.flag() {
             Move::QUIET | Move::DOUBLE_PUSH => self.move_quiet(mv.to(), mv.from()),
             Move::CASTLE_00 => {
@@ -529,7 +1421,7 @@ impl Position {
             }
             Move::EN_PASSANT => {
                 self.move_quiet(mv.to(), mv.from());
-                self.set_square(
+                self.add_piece(
                     (mv.to() as i32 + Direction::relative(Direction::South, 1 - state.turn) as i32)
                         as usize,
                     Piece::PAWN,
@@ -537,17 +1429,17 @@ impl Position {
                 )
             }
             Move::PR_N | Move::PR_B | Move::PR_R | Move::PR_Q => {
-                self.unset_square(mv.to());
-                self.set_square(mv.from(), Piece::PAWN, 1 - state.turn)
+                self.remove_piece(mv.to());
+                self.add_piece(mv.from(), Piece::PAWN, 1 - state.turn)
             }
             Move::PC_N | Move::PC_B | Move::PC_R | Move::PC_Q => {
-                self.unset_square(mv.to());
-                self.set_square(mv.to(), state.captured.unwrap(), state.turn);
-                self.set_square(mv.from(), Piece::PAWN, 1 - state.turn);
+                self.remove_piece(mv.to());
+                self.add_piece(mv.to(), state.captured.unwrap(), state.turn);
+                self.add_piece(mv.from(), Piece::PAWN, 1 - state.turn);
             }
             Move::CAPTURE => {
                 self.move_quiet(mv.to(), mv.from());
-                self.set_square(mv.to(), state.captured.unwrap(), state.turn);
+                self.add_piece(mv.to(), state.captured.unwrap(), state.turn);
             }
             _ => {
                 unreachable!("Invalid move flag")
@@ -555,11 +1447,349 @@ impl Position {
         }
 
         self.update_checks();
+        debug_assert_eq!(
+            self.hash(true),
+            self.recompute_hash(),
+            "Incremental hash diverged after undoing {mv}"
+        );
+    }
+    /// Passes the turn to the opponent without moving a piece, as used by null move pruning.
+    /// It mustn't be called while in check
+    pub fn make_null_move(&mut self) {
+        debug_assert!(!self.in_check(), "Null move made while in check");
+        self.key_history.push(self.repetition_key());
+        self.moves.push(Move::NULL);
+        self.follow_redo(Move::NULL);
+        let state = self.actual_state();
+        self.ply += 1;
+        self.history.push(State {
+            turn: 1 - state.turn,
+            castling: state.castling,
+            captured: None,
+            ep: None,
+            hm: state.hm + 1,
+            fm: if state.turn == Color::BLACK {
+                state.fm + 1
+            } else {
+                state.fm
+            },
+        });
+        self.hash ^= self.keys.side;
+        self.update_checks();
+    }
+    /// Undoes a null move
+    pub fn undo_null_move(&mut self) {
+        self.history.pop();
+        self.key_history.pop();
+        self.moves.pop();
+        self.ply -= 1;
+        self.hash ^= self.keys.side;
+        self.update_checks();
+    }
+    /// Makes a move, runs a closure on the new position and undoes the move before returning what
+    /// the closure returned. The closure must leave the position as it got it
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// let mv = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    /// let replies = pos.with_move(mv, |pos| pos.legal().count());
+    /// assert_eq!(replies, 20);
+    /// assert_eq!(pos.fen(), Position::START_FEN);
+    /// ```
+    #[inline(always)]
+    pub fn with_move<T>(&mut self, mv: Move, f: impl FnOnce(&mut Position) -> T) -> T {
+        self.make_move(mv);
+        let result = f(self);
+        self.undo_move(mv);
+        result
+    }
+    /// Undoes the last move made, null moves included, and returns it. Returns `None` if there
+    /// are no moves to undo
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// let mv = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    /// pos.make_move(mv);
+    /// assert_eq!(pos.undo(), Some(mv));
+    /// assert_eq!(pos.undo(), None);
+    /// ```
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = *self.moves.last()?;
+        if mv.is_null() {
+            self.undo_null_move();
+        } else {
+            self.undo_move(mv);
+        }
+        self.redo_moves.push(mv);
+        Some(mv)
+    }
+    /// Makes again the last move taken back with [Position::undo] and returns it. Making any
+    /// other move forgets the undone moves. Returns `None` if there are no moves to redo
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let mut pos = Position::default();
+    /// let mv = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    /// pos.make_move(mv);
+    /// pos.undo();
+    /// assert_eq!(pos.redo(), Some(mv));
+    /// assert_eq!(pos.redo(), None);
+    /// ```
+    pub fn redo(&mut self) -> Option<Move> {
+        let mv = *self.redo_moves.last()?;
+        if mv.is_null() {
+            self.make_null_move();
+        } else {
+            self.make_move(mv);
+        }
+        Some(mv)
+    }
+    // Keeps the moves to redo while the line being made is the one that was undone
+    #[inline(always)]
+    fn follow_redo(&mut self, mv: Move) {
+        if self.redo_moves.last() == Some(&mv) {
+            self.redo_moves.pop();
+        } else {
+            self.redo_moves.clear();
+        }
+    }
+    /// Gets the board as a string from black's perspective (a1 in the top right corner)
+    pub fn to_string_flipped(&self) -> String {
+        self.render(false, true, 0)
+    }
+    /// Gets the board as a string like [Display](fmt::Display), with the start and destination
+    /// squares of a move between brackets. Adjacent highlighted squares share a `|`
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// let board = pos.to_string_highlighting(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH));
+    /// assert!(board.contains("4 | . . . .[.]. . . |"));
+    /// assert!(board.contains("2 | P P P P[P]P P P |"));
+    /// ```
+    pub fn to_string_highlighting(&self, mv: Move) -> String {
+        let highlight = if mv.is_null() {
+            0
+        } else {
+            1u64 << mv.from() | 1u64 << mv.to()
+        };
+        self.render(false, false, highlight)
+    }
+    /// Draws the board, optionally with unicode figurines, from black's perspective and with
+    /// some squares highlighted
+    fn render(&self, unicode: bool, flipped: bool, highlight: u64) -> String {
+        let ranks: [usize; 8] = if flipped {
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        } else {
+            [7, 6, 5, 4, 3, 2, 1, 0]
+        };
+        let mut s = String::from("  +-----------------+\n");
+        for rank in ranks {
+            // The separators around each square, where the highlight brackets go
+            let mut separators = [' '; 9];
+            let mut cells = [' '; 8];
+            for i in 0..8 {
+                let file = if flipped { 7 - i } else { i };
+                if let Some(piece) = self.piece_on(rank * 8 + file) {
+                    let color = self.color_on(rank * 8 + file).unwrap();
+                    let mut chr = Piece::to_char(piece);
+                    if unicode {
+                        chr = Piece::to_unicode(piece, color);
+                    } else if color == Color::WHITE {
+                        chr = chr.to_ascii_uppercase();
+                    }
+                    cells[i] = chr;
+                } else if unicode {
+                    cells[i] = '·';
+                } else {
+                    cells[i] = '.';
+                }
+                if highlight & (1u64 << (rank * 8 + file)) != 0 {
+                    separators[i] = if separators[i] == ']' { '|' } else { '[' };
+                    separators[i + 1] = ']';
+                }
+            }
+            s.push_str(&format!("{} |", rank + 1));
+            for i in 0..8 {
+                s.push(separators[i]);
+                s.push(cells[i]);
+            }
+            s.push(separators[8]);
+            s.push_str("|\n");
+        }
+        s.push_str("  +-----------------+\n");
+        if flipped {
+            s.push_str("    h g f e d c b a");
+        } else {
+            s.push_str("    a b c d e f g h");
+        }
+        s
+    }
+    /// Gets the board as rows of chars, uppercase for white, lowercase for black and a space for
+    /// empty squares. Rows go from rank 8 to rank 1 and columns from file a to file h, so
+    /// `grid[0][0]` is a8 and `grid[7][7]` is h1, as the board is displayed
+    ///
+    /// ```
+    /// use turbochess::Position;
+    ///
+    /// let grid = Position::default().to_grid();
+    /// assert_eq!(grid[0][4], 'k');
+    /// assert_eq!(grid[7][4], 'K');
+    /// assert_eq!(grid[4][4], ' ');
+    /// ```
+    pub fn to_grid(&self) -> [[char; 8]; 8] {
+        let mut grid = [[' '; 8]; 8];
+        for (row, cells) in grid.iter_mut().enumerate() {
+            for (file, cell) in cells.iter_mut().enumerate() {
+                let square = (7 - row) * 8 + file;
+                if let Some(piece) = self.piece_on(square) {
+                    *cell = Piece::to_char(piece);
+                    if self.color_on(square) == Some(Color::WHITE) {
+                        cell.make_ascii_uppercase();
+                    }
+                }
+            }
+        }
+        grid
     }
-    /// Gets the FEN notation of the current position
+    /// Gets the board as a string using unicode chess figurines. Same as formatting with `{:#}`
+    pub fn to_unicode_string(&self) -> String {
+        format!("{self:#}")
+    }
+    /// Gets the move in standard algebraic notation (SAN). The move must be legal
+    pub fn to_san(&self, mv: Move) -> String {
+        let mut san = String::new();
+        let piece = self.piece_on(mv.from()).unwrap();
+        if mv.flag() == Move::CASTLE_00 {
+            san.push_str("O-O");
+        } else if mv.flag() == Move::CASTLE_000 {
+            san.push_str("O-O-O");
+        } else {
+            if piece == Piece::PAWN {
+                if mv.is_capture() {
+                    san.push_str(&Square::to_string(mv.from())[..1]);
+                }
+            } else {
+                san.push(Piece::to_char(piece).to_ascii_uppercase());
+                // Disambiguate between pieces of the same type that can go to the same square
+                let mut others = 0u64;
+                for other in self.legal() {
+                    if other.to() == mv.to()
+                        && other.from() != mv.from()
+                        && self.piece_on(other.from()) == Some(piece)
+                    {
+                        others |= 1u64 << other.from();
+                    }
+                }
+                if others != 0 {
+                    let from = Square::to_string(mv.from());
+                    if others & BitBoard::file(Square::file(mv.from())) == 0 {
+                        san.push_str(&from[..1]);
+                    } else if others & BitBoard::rank(Square::rank(mv.from())) == 0 {
+                        san.push_str(&from[1..]);
+                    } else {
+                        san.push_str(&from);
+                    }
+                }
+            }
+            if mv.is_capture() {
+                san.push('x');
+            }
+            san.push_str(&Square::to_string(mv.to()));
+            if let Some(p) = mv.promotion_piece() {
+                san.push('=');
+                san.push(Piece::to_char(p).to_ascii_uppercase());
+            }
+        }
+        san.extend(self.check_suffix(mv));
+        san
+    }
+    // Gets '+' if a move gives check, or '#' if it gives checkmate
+    fn check_suffix(&self, mv: Move) -> Option<char> {
+        let mut next = self.clone();
+        next.make_move(mv);
+        if !next.in_check() {
+            None
+        } else if !next.has_legal_moves() {
+            Some('#')
+        } else {
+            Some('+')
+        }
+    }
+    /// Gets the move in long algebraic notation (LAN): the piece letter (none for pawns), both
+    /// squares separated by `-` or `x`, the promotion and the check suffix. Castling is written
+    /// as in SAN, `O-O` or `O-O-O`. The move must be legal
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// assert_eq!(pos.to_lan(Move::new(Square::G1, Square::F3, Move::QUIET)), "Ng1-f3");
+    /// assert_eq!(pos.to_lan(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)), "e2-e4");
+    /// ```
+    pub fn to_lan(&self, mv: Move) -> String {
+        let mut lan = String::new();
+        if mv.flag() == Move::CASTLE_00 {
+            lan.push_str("O-O");
+        } else if mv.flag() == Move::CASTLE_000 {
+            lan.push_str("O-O-O");
+        } else {
+            let piece = self.piece_on(mv.from()).unwrap();
+            if piece != Piece::PAWN {
+                lan.push(Piece::to_char(piece).to_ascii_uppercase());
+            }
+            lan.push_str(&Square::to_string(mv.from()));
+            lan.push(if mv.is_capture() { 'x' } else { '-' });
+            lan.push_str(&Square::to_string(mv.to()));
+            if let Some(p) = mv.promotion_piece() {
+                lan.push('=');
+                lan.push(Piece::to_char(p).to_ascii_uppercase());
+            }
+        }
+        lan.extend(self.check_suffix(mv));
+        lan
+    }
+    /// Wraps a move so it's displayed in SAN instead of UCI
+    ///
+    /// ```
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let pos = Position::default();
+    /// let mv = Move::new(Square::G1, Square::F3, Move::QUIET);
+    /// assert_eq!(format!("{}", pos.san(mv)), "Nf3");
+    /// ```
+    pub fn san(&self, mv: Move) -> SanMove<'_> {
+        SanMove(self, mv)
+    }
+    /// Gets the FEN notation of the current position. As only the standard castling rooks are
+    /// supported, the castling rights are always written as `KQkq`
 
     #[inline(always)]
     pub fn fen(&self) -> String {
+        self.fen_with(false)
+    }
+    /// Gets the Shredder-FEN notation of the current position, used for Chess960, where the
+    /// castling rights are the files of the rooks (`HAha` instead of `KQkq`)
+    ///
+    /// ```
+    /// use turbochess::Position;
+    ///
+    /// assert_eq!(
+    ///     Position::default().fen_chess960(),
+    ///     "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+    /// );
+    /// ```
+    pub fn fen_chess960(&self) -> String {
+        self.fen_with(true)
+    }
+    // Writes the FEN, with the castling rights as rook files in Shredder-FEN
+    fn fen_with(&self, shredder: bool) -> String {
         let state = self.actual_state();
         let mut pieces = String::new();
         for r in (0..8).rev() {
@@ -588,41 +1818,325 @@ impl Position {
                 pieces.push_str("/")
             }
         }
-        let mut castling = String::from("-");
-        if state.castling != 0 {
-            castling = String::new();
+        let mut castling = String::from("-");
+        if state.castling != 0 {
+            castling = String::new();
+
+            for (side, ch, rook) in [
+                (State::WHITE_00, 'K', State::SHORT_ROOK[Color::WHITE]),
+                (State::WHITE_000, 'Q', State::LONG_ROOK[Color::WHITE]),
+                (State::BLACK_00, 'k', State::SHORT_ROOK[Color::BLACK]),
+                (State::BLACK_000, 'q', State::LONG_ROOK[Color::BLACK]),
+            ] {
+                if !state.can_castle(side) {
+                    continue;
+                }
+                if shredder {
+                    let file = (b'a' + Square::file(rook) as u8) as char;
+                    castling.push(if ch.is_ascii_uppercase() {
+                        file.to_ascii_uppercase()
+                    } else {
+                        file
+                    });
+                } else {
+                    castling.push(ch);
+                }
+            }
+        }
+        let mut ep = String::from("-");
+        if let Some(s) = state.ep {
+            ep = Square::to_string(s)
+        }
+        format!(
+            "{pieces} {} {castling} {ep} {} {}",
+            if state.turn == Color::WHITE { "w" } else { "b" },
+            state.hm,
+            state.fm
+        )
+    }
+    /// Gets all the opponent attackers from a square
+    #[inline(always)]
+    pub fn attackers_from(&self, s: usize, color: usize, occ: u64) -> u64 {
+        (PAWN_ATTACKS[1 - color][s] & self.pieces_bb[color][Piece::PAWN])
+            | (KNIGHT_MASK[s]) & self.pieces_bb[color][Piece::KNIGHT]
+            | (d12_moves(s, occ)) & self.d12_sliders(color)
+            | (hv_moves(s, occ)) & self.hv_sliders(color)
+            | (KING_MASK[s]) & self.pieces_bb[color][Piece::KING]
+    }
+    /// Gets the square and type of the least valuable piece of a color attacking a square. Only
+    /// the pieces inside the occupancy are considered, which also blocks the sliders
+    pub fn least_valuable_attacker(
+        &self,
+        sq: usize,
+        by: usize,
+        occ: u64,
+    ) -> Option<(usize, usize)> {
+        let hv = hv_moves(sq, occ);
+        let d12 = d12_moves(sq, occ);
+        let masks = [
+            PAWN_ATTACKS[1 - by][sq],
+            KNIGHT_MASK[sq],
+            d12,
+            hv,
+            hv | d12,
+            KING_MASK[sq],
+        ];
+        for (p, mask) in masks.iter().enumerate() {
+            let b1 = mask & self.pieces_bb[by][p] & occ;
+            if b1 != 0 {
+                return Some((b1.bit_scan(), p));
+            }
+        }
+        None
+    }
+    // Value gained by the capture itself and value of the piece left on the target square
+    fn capture_values(&self, mv: Move) -> (i32, i32) {
+        let mut gain = if mv.is_en_passant() {
+            Piece::VALUES[Piece::PAWN]
+        } else {
+            self.piece_on(mv.to()).map_or(0, |p| Piece::VALUES[p])
+        };
+        let mut piece = self.piece_on(mv.from()).unwrap();
+        if let Some(promo) = mv.promotion_piece() {
+            gain += Piece::VALUES[promo] - Piece::VALUES[Piece::PAWN];
+            piece = promo;
+        }
+        (gain, Piece::VALUES[piece])
+    }
+    // Occupancy once the moving piece (and the pawn captured en passant) left their squares
+    fn occupancy_after(&self, mv: Move) -> u64 {
+        let mut occ = self.occupancy() & !(1u64 << mv.from());
+        if mv.is_en_passant() {
+            occ &= !(1u64 << (mv.from() & !7 | mv.to() & 7));
+        }
+        occ
+    }
+    /// Static exchange evaluation: the material won by the side to move after the exchange of
+    /// pieces started by a move on its target square, with both sides recapturing with their
+    /// least valuable piece and stopping when it doesn't pay off. Pins are ignored, and only the
+    /// first move can promote
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// // The pawn on e5 is defended, so taking it with the rook loses the exchange
+    /// let pos = Position::from_str("1k1r4/1pp4p/p4p2/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1").unwrap();
+    /// assert_eq!(pos.see(Move::new(Square::E1, Square::E5, Move::CAPTURE)), -400);
+    /// ```
+    pub fn see(&self, mv: Move) -> i32 {
+        let (captured, mut next_victim) = self.capture_values(mv);
+        let mut occ = self.occupancy_after(mv);
+        let mut side = 1 - self.actual_state().turn;
+        // There are at most 32 pieces, so at most 32 captures on the square
+        let mut gains = [0; 32];
+        gains[0] = captured;
+        let mut depth = 0;
+        while let Some((sq, p)) = self.least_valuable_attacker(mv.to(), side, occ) {
+            occ &= !(1u64 << sq);
+            // The king can't recapture on a defended square
+            if p == Piece::KING
+                && self
+                    .least_valuable_attacker(mv.to(), 1 - side, occ)
+                    .is_some()
+            {
+                break;
+            }
+            depth += 1;
+            gains[depth] = next_victim - gains[depth - 1];
+            next_victim = Piece::VALUES[p];
+            side = 1 - side;
+        }
+        // Every side can stop capturing when it doesn't pay off
+        while depth > 0 {
+            gains[depth - 1] = -(-gains[depth - 1]).max(gains[depth]);
+            depth -= 1;
+        }
+        gains[0]
+    }
+    /// Checks if the static exchange evaluation of a move is at least the threshold. It's the
+    /// same as `see(mv) >= threshold`, but stops the exchange as soon as the outcome is known
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::{Move, Square}, Position};
+    ///
+    /// let pos = Position::from_str("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1").unwrap();
+    /// let mv = Move::new(Square::E1, Square::E5, Move::CAPTURE);
+    /// assert!(pos.is_good_capture(mv, 100));
+    /// assert!(!pos.is_good_capture(mv, 101));
+    /// ```
+    pub fn is_good_capture(&self, mv: Move, threshold: i32) -> bool {
+        let (captured, moving) = self.capture_values(mv);
+        // What is left after the capture if the opponent doesn't recapture
+        let mut swap = captured - threshold;
+        if swap < 0 {
+            return false;
+        }
+        // What is left after losing the moving piece
+        swap = moving - swap;
+        if swap <= 0 {
+            return true;
+        }
+        let mut occ = self.occupancy_after(mv);
+        let mut side = self.actual_state().turn;
+        // 1 while the side to move is winning the exchange
+        let mut res = 1;
+        loop {
+            side = 1 - side;
+            let Some((sq, p)) = self.least_valuable_attacker(mv.to(), side, occ) else {
+                break;
+            };
+            res ^= 1;
+            occ &= !(1u64 << sq);
+            if p == Piece::KING {
+                // A king recapture only stands if the other side has no attackers left
+                return if self
+                    .least_valuable_attacker(mv.to(), 1 - side, occ)
+                    .is_some()
+                {
+                    res == 0
+                } else {
+                    res == 1
+                };
+            }
+            swap = Piece::VALUES[p] - swap;
+            if swap < res {
+                break;
+            }
+        }
+        res == 1
+    }
+    /// Counts the pseudo-legal destination squares of all the pieces of a color, ignoring pins,
+    /// checks and whose turn it is
+    pub fn mobility(&self, color: usize) -> usize {
+        let occ = self.occupancy();
+        let targets = !self.colors(color);
+        let mut count = 0;
+
+        // Pawn pushes and captures
+        let mut b1 = BitBoard::shift_dir(
+            self.pieces_bb[color][Piece::PAWN],
+            Direction::relative(Direction::North, color),
+        ) & !occ;
+        count += b1.bit_count();
+        b1 = BitBoard::shift_dir(b1, Direction::relative(Direction::North, color))
+            & !occ
+            & BitBoard::relative_rank(4, color);
+        count += b1.bit_count();
+        for s in BitBoard::squares(self.pieces_bb[color][Piece::PAWN]) {
+            count += (PAWN_ATTACKS[color][s] & self.colors(1 - color)).bit_count();
+        }
+
+        // Knight moves
+        for s in BitBoard::squares(self.pieces_bb[color][Piece::KNIGHT]) {
+            count += (KNIGHT_MASK[s] & targets).bit_count();
+        }
+
+        // HV moves
+        for s in BitBoard::squares(self.hv_sliders(color)) {
+            count += (hv_moves(s, occ) & targets).bit_count();
+        }
+
+        // D12 moves
+        for s in BitBoard::squares(self.d12_sliders(color)) {
+            count += (d12_moves(s, occ) & targets).bit_count();
+        }
 
-            if state.can_castle(State::WHITE_00) {
-                castling.push('K');
+        // King moves
+        count += (KING_MASK[self.king(color)] & targets).bit_count();
+
+        count
+    }
+    /// Checks if a pawn of a color on a square is passed, that is, no enemy pawn is in front of
+    /// it on its file or the adjacent files
+    pub fn is_passed_pawn(&self, sq: usize, color: usize) -> bool {
+        front_span_wide(sq, color) & self.pieces_bb[1 - color][Piece::PAWN] == 0
+    }
+    /// Gets a bitboard with all the passed pawns of a color
+    pub fn passed_pawns(&self, color: usize) -> u64 {
+        let mut passed = 0u64;
+        let mut b1 = self.pieces_bb[color][Piece::PAWN];
+        while b1 != 0 {
+            if self.is_passed_pawn(b1.bit_scan(), color) {
+                passed |= b1.get_lsb();
             }
-            if state.can_castle(State::WHITE_000) {
-                castling.push('Q');
+            b1 = b1.pop_lsb();
+        }
+        passed
+    }
+    /// Gets a bitboard with the pawns of a color that have another friendly pawn on their file
+    pub fn doubled_pawns(&self, color: usize) -> u64 {
+        let pawns = self.pieces_bb[color][Piece::PAWN];
+        let mut doubled = 0u64;
+        for f in 0..8 {
+            let b1 = pawns & BitBoard::file(f);
+            if b1.bit_count() > 1 {
+                doubled |= b1;
             }
-            if state.can_castle(State::BLACK_00) {
-                castling.push('k');
+        }
+        doubled
+    }
+    /// Gets a bitboard with the pawns of a color that have no friendly pawns on the adjacent files
+    pub fn isolated_pawns(&self, color: usize) -> u64 {
+        let pawns = self.pieces_bb[color][Piece::PAWN];
+        let mut isolated = 0u64;
+        for f in 0..8 {
+            if pawns & BitBoard::adjacent_files(f) == 0 {
+                isolated |= pawns & BitBoard::file(f);
             }
-            if state.can_castle(State::BLACK_000) {
-                castling.push('q');
+        }
+        isolated
+    }
+    /// Gets a bitboard with the files that have no pawns of either color
+    pub fn open_files(&self) -> u64 {
+        let pawns =
+            self.pieces_bb[Color::WHITE][Piece::PAWN] | self.pieces_bb[Color::BLACK][Piece::PAWN];
+        let mut open = 0u64;
+        for f in 0..8 {
+            let file = BitBoard::file(f);
+            if pawns & file == 0 {
+                open |= file;
             }
         }
-        let mut ep = String::from("-");
-        if let Some(s) = state.ep {
-            ep = Square::to_string(s)
+        open
+    }
+    /// Gets a bitboard with the files that have no pawns of a color, but have some of the enemy
+    pub fn half_open_files(&self, color: usize) -> u64 {
+        let mut half_open = 0u64;
+        for f in 0..8 {
+            let file = BitBoard::file(f);
+            if self.pieces_bb[color][Piece::PAWN] & file == 0
+                && self.pieces_bb[1 - color][Piece::PAWN] & file != 0
+            {
+                half_open |= file;
+            }
         }
-        format!(
-            "{pieces} {} {castling} {ep} {} {}",
-            if state.turn == Color::WHITE { "w" } else { "b" },
-            state.hm,
-            state.fm
-        )
+        half_open
     }
-    /// Gets all the opponent attackers from a square
-    #[inline(always)]
-    pub fn attackers_from(&self, s: usize, color: usize, occ: u64) -> u64 {
-        (PAWN_ATTACKS[1 - color][s] & self.pieces_bb[color][Piece::PAWN])
-            | (KNIGHT_MASK[s]) & self.pieces_bb[color][Piece::KNIGHT]
-            | (d12_moves(s, occ)) & self.d12_sliders(color)
-            | (hv_moves(s, occ)) & self.hv_sliders(color)
+    /// Counts the enemy pieces (other than the king) that attack the king of a color or any square
+    /// next to it
+    pub fn king_zone_attackers(&self, king_color: usize) -> usize {
+        let enemy = 1 - king_color;
+        let king = self.king(king_color);
+        let zone = KING_MASK[king] | (1u64 << king);
+        let occ = self.occupancy();
+        let mut count = 0;
+        for p in Piece::PAWN..Piece::KING {
+            for s in BitBoard::squares(self.pieces_bb[enemy][p]) {
+                let attacks = match p {
+                    Piece::PAWN => PAWN_ATTACKS[enemy][s],
+                    Piece::KNIGHT => KNIGHT_MASK[s],
+                    Piece::BISHOP => d12_moves(s, occ),
+                    Piece::ROOK => hv_moves(s, occ),
+                    _ => hv_moves(s, occ) | d12_moves(s, occ),
+                };
+                if attacks & zone != 0 {
+                    count += 1;
+                }
+            }
+        }
+        count
     }
     /// Gets the occupancy of the board
     #[inline(always)]
@@ -639,32 +2153,56 @@ impl Position {
     pub fn pinned(&self) -> u64 {
         self.pin_d12 | self.pin_hv
     }
+    /// Gets the rays of the orthogonal pins on the side to move: the squares between the king and
+    /// each pinning rook or queen, plus the pinner itself. Intersect it with our pieces to get the
+    /// pinned ones
+    #[inline(always)]
+    pub fn pinned_hv(&self) -> u64 {
+        self.pin_hv
+    }
+    /// Gets the rays of the diagonal pins on the side to move, like [Position::pinned_hv] does for
+    /// the orthogonal ones
+    #[inline(always)]
+    pub fn pinned_d12(&self) -> u64 {
+        self.pin_d12
+    }
+    /// Gets the squares attacked by the opponent, computed with our king removed from the
+    /// occupancy so it can't hide from a slider by stepping back along its ray
+    #[inline(always)]
+    pub fn danger(&self) -> u64 {
+        self.danger
+    }
     /// Gets all the attacked squares from the opponent
     #[inline(always)]
     pub fn attacks(&self) -> u64 {
+        self.attack_map(1 - self.actual_state().turn)
+    }
+    /// Gets all the squares attacked by a color. The enemy king is removed from the occupancy, so
+    /// the squares behind it on a slider's ray are also attacked
+    #[inline(always)]
+    pub fn attack_map(&self, color: usize) -> u64 {
         let mut attacks = 0;
-        let state = self.actual_state();
-        let o_king = self.king(state.turn);
-        let e_king = self.king(1 - state.turn);
-        let occ = self.occupancy() & !(1u64 << o_king); // Remove our king for fixing check slider
+        let o_king = self.king(1 - color);
+        let e_king = self.king(color);
+        let occ = self.occupancy() & !(1u64 << o_king); // Remove the king for fixing check slider
 
         // General use variables
-        let mut b1 = 0u64;
-        let mut s = 0usize;
+        let mut b1: u64;
+        let mut s: usize;
 
         // King attacks
         attacks |= KING_MASK[e_king];
 
         // Pawn attacks
-        b1 = self.pieces_bb[1 - state.turn][Piece::PAWN];
+        b1 = self.pieces_bb[color][Piece::PAWN];
         while b1 != 0 {
             s = b1.bit_scan();
-            attacks |= PAWN_ATTACKS[1 - state.turn][s];
+            attacks |= PAWN_ATTACKS[color][s];
             b1 = b1.pop_lsb();
         }
 
         // Knight attacks
-        b1 = self.pieces_bb[1 - state.turn][Piece::KNIGHT];
+        b1 = self.pieces_bb[color][Piece::KNIGHT];
         while b1 != 0 {
             s = b1.bit_scan();
             attacks |= KNIGHT_MASK[s];
@@ -672,7 +2210,7 @@ impl Position {
         }
 
         // HV attacks
-        b1 = self.hv_sliders(1 - state.turn);
+        b1 = self.hv_sliders(color);
         while b1 != 0 {
             s = b1.bit_scan();
             attacks |= hv_moves(s, occ);
@@ -680,7 +2218,7 @@ impl Position {
         }
 
         // D12 attacks
-        b1 = self.d12_sliders(1 - state.turn);
+        b1 = self.d12_sliders(color);
         while b1 != 0 {
             s = b1.bit_scan();
             attacks |= d12_moves(s, occ);
@@ -791,14 +2329,435 @@ impl Position {
 
         (checkmask, pin_hv, pin_d12)
     }
+    /// Finds the pieces (of any color) that are the only piece between the king of a color and an
+    /// enemy slider aiming at it, and the sliders that have one of the king's own pieces in the way
+    fn slider_blockers(&self, king_color: usize) -> (u64, u64) {
+        let king = self.king(king_color);
+        let enemy = 1 - king_color;
+        let occ = self.occupancy();
+        let snipers = (hv_moves(king, 0) & self.hv_sliders(enemy))
+            | (d12_moves(king, 0) & self.d12_sliders(enemy));
+        let mut blockers = 0;
+        let mut pinners = 0;
+        for s in BitBoard::squares(snipers) {
+            let b = between(s, king) & occ;
+            if b.bit_count() == 1 {
+                blockers |= b;
+                if b & self.colors(king_color) != 0 {
+                    pinners |= 1u64 << s;
+                }
+            }
+        }
+        (blockers, pinners)
+    }
+    /// Gets the pieces of both colors that are the only piece between the king of a color and an
+    /// enemy slider. Ours are pinned, and the enemy ones would give a discovered check if they
+    /// moved out of the line
+    pub fn blockers_for_king(&self, color: usize) -> u64 {
+        self.slider_blockers(color).0
+    }
+    /// Gets the enemy sliders that pin a piece of a color to its king
+    pub fn pinners(&self, color: usize) -> u64 {
+        self.slider_blockers(color).1
+    }
+    /// Checks if a move uncovers an attack of one of our sliders on the enemy king, by moving the
+    /// piece that was in the way out of the line
+    pub fn discovers_check(&self, mv: Move) -> bool {
+        let us = self.actual_state().turn;
+        let king = self.king(1 - us);
+        let (blockers, _) = self.slider_blockers(1 - us);
+        let (from, to) = (mv.from(), mv.to());
+        if blockers & self.colors(us) & (1u64 << from) == 0 {
+            return false;
+        }
+        // Moving along the line towards the king or the slider keeps blocking it
+        line(king, to) & (1u64 << from) == 0 && line(king, from) & (1u64 << to) == 0
+    }
     /// Check if the actual player is on check
     #[inline(always)]
     pub fn in_check(&self) -> bool {
         self.checkmask != u64::MAX
     }
+    /// Checks if the side to move is checkmated
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check() && !self.has_legal_moves()
+    }
+    /// Checks if the side to move is stalemated
+    pub fn is_stalemate(&self) -> bool {
+        !self.in_check() && !self.has_legal_moves()
+    }
+    /// Checks if any piece of a color attacks a square
+    pub fn is_square_attacked(&self, sq: usize, by: usize) -> bool {
+        self.attackers_from(sq, by, self.occupancy()) != 0
+    }
+    /// Checks if the king of a color is attacked, whether it's its turn or not
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{types::Color, Position};
+    ///
+    /// let pos = Position::from_str("4k3/8/8/8/8/8/8/4RK2 w - - 0 1").unwrap();
+    /// assert!(pos.is_in_check(Color::BLACK));
+    /// assert!(!pos.is_in_check(Color::WHITE));
+    /// ```
+    pub fn is_in_check(&self, color: usize) -> bool {
+        self.is_square_attacked(self.king(color), 1 - color)
+    }
+    /// Calculates the pseudo-legal moves in the position, which are the moves the pieces can make
+    /// without looking at checks and pins. Castling only needs the rights and the squares between
+    /// the king and the rook to be empty. The legal moves are always a subset of these
+    pub fn pseudo_legal(&self) -> MoveList {
+        let mut list = MoveList::new();
+        let state = self.actual_state();
+        let us = state.turn;
+        let occ = self.occupancy();
+        let en = self.colors(1 - us);
+        let em = !occ;
+        let last_rank = BitBoard::relative_rank(8, us);
+
+        // Pawn moves
+        let up = Direction::relative(Direction::North, us) as i32;
+        for s in BitBoard::squares(self.pieces_bb[us][Piece::PAWN]) {
+            let to = (s as i32 + up) as usize;
+            if em & (1u64 << to) != 0 {
+                if last_rank & (1u64 << to) != 0 {
+                    list.add_promotions(s, to, false);
+                } else {
+                    list.add(s, to, Move::QUIET);
+                    let to2 = (to as i32 + up) as usize;
+                    if BitBoard::relative_rank(2, us) & (1u64 << s) != 0 && em & (1u64 << to2) != 0
+                    {
+                        list.add(s, to2, Move::DOUBLE_PUSH);
+                    }
+                }
+            }
+            let captures = PAWN_ATTACKS[us][s] & en;
+            list.extend_promotions(s, captures & last_rank, true);
+            list.extend(s, captures & !last_rank, Move::CAPTURE);
+            if let Some(ep) = state.ep {
+                if PAWN_ATTACKS[us][s] & (1u64 << ep) != 0 {
+                    list.add(s, ep, Move::EN_PASSANT);
+                }
+            }
+        }
+
+        // Piece moves
+        for p in Piece::KNIGHT..=Piece::KING {
+            for s in BitBoard::squares(self.pieces_bb[us][p]) {
+                let attacks = match p {
+                    Piece::KNIGHT => KNIGHT_MASK[s],
+                    Piece::BISHOP => d12_moves(s, occ),
+                    Piece::ROOK => hv_moves(s, occ),
+                    Piece::QUEEN => hv_moves(s, occ) | d12_moves(s, occ),
+                    _ => KING_MASK[s],
+                };
+                list.extend(s, attacks & en, Move::CAPTURE);
+                list.extend(s, attacks & em, Move::QUIET);
+            }
+        }
+
+        // Castling
+        if state.can_castle(State::SHORT[us]) && oo_blockers(us) & occ == 0 {
+            list.add(
+                State::KING_START[us],
+                State::SHORT_TARGET[us],
+                Move::CASTLE_00,
+            );
+        }
+        if state.can_castle(State::LONG[us]) && ooo_blockers(us) & occ == 0 {
+            list.add(
+                State::KING_START[us],
+                State::LONG_KING_TARGET[us],
+                Move::CASTLE_000,
+            );
+        }
+
+        list
+    }
+    /// Picks a legal move uniformly at random, or returns `None` if there are no legal moves
+    ///
+    /// ```
+    /// use rand::{rngs::SmallRng, SeedableRng};
+    /// use turbochess::Position;
+    ///
+    /// let mut rng = SmallRng::seed_from_u64(42);
+    /// let pos = Position::default();
+    /// let mv = pos.random_legal(&mut rng).unwrap();
+    /// assert!(pos.legal().contains(mv));
+    /// ```
+    #[cfg(feature = "rand")]
+    pub fn random_legal<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<Move> {
+        let moves = self.legal();
+        if moves.count() == 0 {
+            return None;
+        }
+        Some(moves.as_slice()[rng.gen_range(0..moves.count())])
+    }
+    /// Calculates the legal moves by making every pseudo-legal move and keeping the ones that
+    /// don't leave the king attacked. It's much slower than [Position::legal], and is meant as a
+    /// reference to check it against
+    pub fn legal_verified(&self) -> MoveList {
+        let mut list = MoveList::new();
+        let us = self.actual_state().turn;
+        let mut pos = self.clone();
+        for mv in self.pseudo_legal() {
+            if mv.is_castle() {
+                // The king can't castle out of or through check
+                let through = if mv.flag() == Move::CASTLE_00 {
+                    State::KING_START[us] + 1
+                } else {
+                    State::KING_START[us] - 1
+                };
+                if [State::KING_START[us], through]
+                    .into_iter()
+                    .any(|sq| self.is_square_attacked(sq, 1 - us))
+                {
+                    continue;
+                }
+            }
+            pos.make_move(mv);
+            let safe = !pos.is_in_check(us);
+            pos.undo_move(mv);
+            if safe {
+                list.add_raw(mv);
+            }
+        }
+        list
+    }
+    /// Calculates the legal moves in the position, leaving out the ones the options exclude
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::{GenOptions, Position};
+    ///
+    /// let pos = Position::from_str("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    /// let options = GenOptions { queen_promotions_only: true };
+    /// assert_eq!(pos.legal_with(options).count_promotions(), 1);
+    /// ```
+    pub fn legal_with(&self, options: GenOptions) -> MoveList {
+        let list = self.legal();
+        if !options.queen_promotions_only {
+            return list;
+        }
+        list.into_iter()
+            .filter(|mv| mv.promotion_piece().unwrap_or(Piece::QUEEN) == Piece::QUEEN)
+            .collect()
+    }
+    /// Adds the legal en passant captures, which have to be checked against the sliders with the
+    /// occupancy after the capture
+    #[inline(always)]
+    fn add_en_passant(&self, list: &mut MoveList) {
+        let state = self.actual_state();
+        let Some(ep) = state.ep else {
+            return;
+        };
+        let o_king = self.king(state.turn);
+        let captured = BitBoard::shift_dir(
+            1u64 << ep,
+            Direction::relative(Direction::South, state.turn),
+        );
+        // It solves a check if it takes the checking pawn or blocks the checking slider
+        if self.checkmask & (1u64 << ep | captured) == 0 {
+            return;
+        }
+        let mut pawns = PAWN_ATTACKS[1 - state.turn][ep] & self.pieces_bb[state.turn][Piece::PAWN];
+        while pawns != 0 {
+            let s = pawns.bit_scan();
+            // Both pawns leave their squares, so check the sliders against the king with the
+            // occupancy after the capture. This covers the pins and the king and the two pawns
+            // on the same rank as a rook
+            let occ = self.occupancy() & !(1u64 << s) & !captured | 1u64 << ep;
+            let attackers = hv_moves(o_king, occ) & self.hv_sliders(1 - state.turn)
+                | d12_moves(o_king, occ) & self.d12_sliders(1 - state.turn);
+            if attackers == 0 {
+                list.add(s, ep, Move::EN_PASSANT);
+            }
+            pawns = pawns.pop_lsb();
+        }
+    }
+    /// Calculates the legal moves when the side to move is in check. Only the king can move in a
+    /// double check. Otherwise the other pieces have to capture the checker or block the check,
+    /// so the squares of the checkmask are visited instead of the moves of every piece. Pinned
+    /// pieces can't do either, as they can only move along the line of their pin
+    #[inline(always)]
+    fn legal_evasions(&self) -> MoveList {
+        let mut list = MoveList::new();
+        let state = self.actual_state();
+        let o_king = self.king(state.turn);
+        let occ = self.occupancy();
+        let en = self.colors(1 - state.turn);
+        let em = !occ;
+
+        let mut b1 = KING_MASK[o_king] & !self.danger;
+        list.extend(o_king, b1 & en, Move::CAPTURE);
+        list.extend(o_king, b1 & em, Move::QUIET);
+
+        // Double check
+        if self.checkmask == 0 {
+            return list;
+        }
+
+        let free = !(self.pin_hv | self.pin_d12);
+        let knights = self.pieces_bb[state.turn][Piece::KNIGHT] & free;
+        let hv = self.hv_sliders(state.turn) & free;
+        let d12 = self.d12_sliders(state.turn) & free;
+        let pawns = self.pieces_bb[state.turn][Piece::PAWN] & free;
+        let promotion = BitBoard::relative_rank(8, state.turn);
+
+        // The checker, or the squares between it and the king
+        let mut targets = self.checkmask & !(1u64 << o_king);
+        while targets != 0 {
+            let t = targets.bit_scan();
+            let capture = en & (1u64 << t) != 0;
+            let flag = if capture { Move::CAPTURE } else { Move::QUIET };
+
+            b1 = KNIGHT_MASK[t] & knights | hv_moves(t, occ) & hv | d12_moves(t, occ) & d12;
+            while b1 != 0 {
+                list.add(b1.bit_scan(), t, flag);
+                b1 = b1.pop_lsb();
+            }
+
+            if capture {
+                b1 = PAWN_ATTACKS[1 - state.turn][t] & pawns;
+                while b1 != 0 {
+                    if promotion & (1u64 << t) != 0 {
+                        list.add_promotions(b1.bit_scan(), t, true);
+                    } else {
+                        list.add(b1.bit_scan(), t, Move::CAPTURE);
+                    }
+                    b1 = b1.pop_lsb();
+                }
+            } else {
+                // Single and double pushes that block the check
+                let behind = BitBoard::shift_dir(
+                    1u64 << t,
+                    Direction::relative(Direction::South, state.turn),
+                );
+                if behind & pawns != 0 {
+                    if promotion & (1u64 << t) != 0 {
+                        list.add_promotions(behind.bit_scan(), t, false);
+                    } else {
+                        list.add(behind.bit_scan(), t, Move::QUIET);
+                    }
+                } else if behind & em != 0
+                    && BitBoard::relative_rank(4, state.turn) & (1u64 << t) != 0
+                {
+                    let start = BitBoard::shift_dir(
+                        behind,
+                        Direction::relative(Direction::South, state.turn),
+                    ) & pawns;
+                    if start != 0 {
+                        list.add(start.bit_scan(), t, Move::DOUBLE_PUSH);
+                    }
+                }
+            }
+            targets = targets.pop_lsb();
+        }
+
+        self.add_en_passant(&mut list);
+        list
+    }
+    /// Checks if there is any legal move, stopping at the first one found instead of generating
+    /// them all. The king is tried first, then the other pieces unless it's a double check
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use turbochess::Position;
+    ///
+    /// assert!(Position::default().has_legal_moves());
+    /// let mate = Position::from_str("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+    /// assert!(!mate.has_legal_moves());
+    /// ```
+    pub fn has_legal_moves(&self) -> bool {
+        let state = self.actual_state();
+        let o_king = self.king(state.turn);
+        let own = self.colors(state.turn);
+        if KING_MASK[o_king] & !self.danger & !own != 0 {
+            return true;
+        }
+        if self.in_check() {
+            // Evasions are few, and there are none but the king moves in a double check
+            return self.checkmask != 0 && self.legal_evasions().count() != 0;
+        }
+
+        let occ = self.occupancy();
+        let en = self.colors(1 - state.turn);
+        let pinned = self.pin_hv | self.pin_d12;
+
+        let mut b1 = self.pieces_bb[state.turn][Piece::KNIGHT] & !pinned;
+        while b1 != 0 {
+            if KNIGHT_MASK[b1.bit_scan()] & !own != 0 {
+                return true;
+            }
+            b1 = b1.pop_lsb();
+        }
+        b1 = self.hv_sliders(state.turn) & !self.pin_d12;
+        while b1 != 0 {
+            let s = b1.bit_scan();
+            let pin = if self.pin_hv & (1u64 << s) != 0 {
+                self.pin_hv
+            } else {
+                u64::MAX
+            };
+            if hv_moves(s, occ) & !own & pin != 0 {
+                return true;
+            }
+            b1 = b1.pop_lsb();
+        }
+        b1 = self.d12_sliders(state.turn) & !self.pin_hv;
+        while b1 != 0 {
+            let s = b1.bit_scan();
+            let pin = if self.pin_d12 & (1u64 << s) != 0 {
+                self.pin_d12
+            } else {
+                u64::MAX
+            };
+            if d12_moves(s, occ) & !own & pin != 0 {
+                return true;
+            }
+            b1 = b1.pop_lsb();
+        }
+
+        // Pawn pushes, with the pawns pinned on a file staying on it
+        let pawns = self.pieces_bb[state.turn][Piece::PAWN];
+        b1 = BitBoard::shift_dir(
+            pawns & !pinned,
+            Direction::relative(Direction::North, state.turn),
+        ) | BitBoard::shift_dir(
+            pawns & !self.pin_d12 & self.pin_hv,
+            Direction::relative(Direction::North, state.turn),
+        ) & self.pin_hv;
+        if b1 & !occ != 0 {
+            return true;
+        }
+        // Pawn captures, with the pawns pinned on a diagonal capturing along it
+        b1 = pawns & !self.pin_hv;
+        while b1 != 0 {
+            let s = b1.bit_scan();
+            let pin = if self.pin_d12 & (1u64 << s) != 0 {
+                self.pin_d12
+            } else {
+                u64::MAX
+            };
+            if PAWN_ATTACKS[state.turn][s] & en & pin != 0 {
+                return true;
+            }
+            b1 = b1.pop_lsb();
+        }
+
+        // Castling needs the square next to the king to be free and safe, which is already a
+        // king move, so only en passant is left
+        let mut list = MoveList::new();
+        self.add_en_passant(&mut list);
+        list.count() != 0
+    }
     /// Calculates all the legal moves in the position
     #[inline(always)]
     pub fn legal(&self) -> MoveList {
+        if self.checkmask != u64::MAX {
+            return self.legal_evasions();
+        }
+
         let mut list = MoveList::new();
         let state = self.actual_state();
         let o_king = self.king(state.turn);
@@ -812,18 +2771,12 @@ impl Position {
         let mut s = 0usize;
         let mut b1 = 0u64;
         let mut b2 = 0u64;
-        let mut b3 = 0u64;
 
         // Generate king moves first
         b1 = KING_MASK[o_king] & !self.danger;
         list.extend(o_king, b1 & en, Move::CAPTURE);
         list.extend(o_king, b1 & em, Move::QUIET);
 
-        // Quick check: If is a double check, only return king moves
-        if self.checkmask == 0 {
-            return list;
-        }
-
         let pinned = self.pin_hv | self.pin_d12;
 
         // Knight moves
@@ -970,60 +2923,6 @@ impl Position {
             );
             b2 = b2.pop_lsb();
         }
-        /*
-               // Pawns that aren't pinned orthogonally
-               b1 = self.pieces_bb[state.turn][Piece::PAWN] & !self.pin_hv;
-               while b1 != 0 {
-                   s = b1.bit_scan();
-                   b2 = if self.pin_d12 & b1.get_lsb() != 0 {
-                       PAWN_ATTACKS[state.turn][s] & en & self.pin_d12
-                   } else {
-                       PAWN_ATTACKS[state.turn][s] & en
-                   };
-                   if b2 & BitBoard::relative_rank(8, state.turn) != 0 {
-                       list.extend_promotions(s, b2 & self.checkmask, true);
-                   } else {
-                       list.extend(s, b2 & self.checkmask, Move::CAPTURE);
-                   }
-                   if let Some(ep) = state.ep {
-                       b2 = PAWN_ATTACKS[state.turn][s] & (1u64 << ep);
-                       // If pinned diagonally and the result is over the pinmask
-                       if self.pin_d12 & b1.get_lsb() != 0 {
-                           list.extend(s, b2 & self.pin_d12 & self.checkmask, Move::EN_PASSANT);
-                       } else {
-                           // If the en passant ocurrs on the same rank as the king and there is a HV on the same rank, then its ilegal
-                           b3 = self.hv_sliders(1 - state.turn) & BitBoard::RANK_1 << (o_king / 8 * 8);
-                           if b3 == 0 {
-                               if self.checkmask
-                                   == BitBoard::shift_dir(
-                                       1u64 << ep,
-                                       Direction::relative(Direction::South, state.turn),
-                                   )
-                               {
-                                   list.extend(s, b2, Move::EN_PASSANT);
-                               } else {
-                                   list.extend(s, b2 & self.checkmask, Move::EN_PASSANT);
-                               }
-                           } else {
-                               while b3 != 0 {
-                                   if between(b3.bit_scan(), o_king) & occ
-                                       != (1u64 << s)
-                                           | BitBoard::shift_dir(
-                                               1u64 << ep,
-                                               Direction::relative(Direction::South, state.turn),
-                                           )
-                                   {
-                                       list.extend(s, b2 & self.checkmask, Move::EN_PASSANT);
-                                   }
-                                   b3 = b3.pop_lsb();
-                               }
-                           }
-                       }
-                   }
-
-                   b1 = b1.pop_lsb();
-               }
-        */
         // Left captures
         b1 = BitBoard::shift_dir(
             self.pieces_bb[state.turn][Piece::PAWN] & !pinned,
@@ -1087,7 +2986,8 @@ impl Position {
             self.pieces_bb[state.turn][Piece::PAWN] & !self.pin_hv & self.pin_d12,
             Direction::relative(Direction::NorthEast, state.turn),
         ) & en
-            & self.pin_d12;
+            & self.pin_d12
+            & self.checkmask;
         b2 = b1 & !BitBoard::relative_rank(8, state.turn);
         while b2 != 0 {
             s = b2.bit_scan();
@@ -1116,7 +3016,8 @@ impl Position {
             self.pieces_bb[state.turn][Piece::PAWN] & !self.pin_hv & self.pin_d12,
             Direction::relative(Direction::NorthWest, state.turn),
         ) & en
-            & self.pin_d12;
+            & self.pin_d12
+            & self.checkmask;
         b2 = b1 & !BitBoard::relative_rank(8, state.turn);
         while b2 != 0 {
             s = b2.bit_scan();
@@ -1141,47 +3042,7 @@ impl Position {
             b2 = b2.pop_lsb();
         }
 
-        // En passant
-        if let Some(ep) = state.ep {
-            b1 = PAWN_ATTACKS[1 - state.turn][ep]
-                & self.pieces_bb[state.turn][Piece::PAWN]
-                & !self.pin_hv;
-            while b1 != 0 {
-                b2 = 1u64 << ep;
-                // Check if pawn can en passant
-                if self.pin_d12 & b1.get_lsb() != 0 {
-                    list.extend(s, b2 & self.pin_d12 & self.checkmask, Move::EN_PASSANT);
-                } else {
-                    // If the en passant ocurrs on the same rank as the king and there is a HV on the same rank, then its ilegal
-                    b3 = self.hv_sliders(1 - state.turn) & BitBoard::RANK_1 << (o_king / 8 * 8);
-                    if b3 == 0 {
-                        if self.checkmask
-                            == BitBoard::shift_dir(
-                                1u64 << ep,
-                                Direction::relative(Direction::South, state.turn),
-                            )
-                        {
-                            list.extend(s, b2, Move::EN_PASSANT);
-                        } else {
-                            list.extend(s, b2 & self.checkmask, Move::EN_PASSANT);
-                        }
-                    } else {
-                        while b3 != 0 {
-                            if between(b3.bit_scan(), o_king) & occ
-                                != (1u64 << s)
-                                    | BitBoard::shift_dir(
-                                        1u64 << ep,
-                                        Direction::relative(Direction::South, state.turn),
-                                    )
-                            {
-                                list.extend(s, b2 & self.checkmask, Move::EN_PASSANT);
-                            }
-                            b3 = b3.pop_lsb();
-                        }
-                    }
-                }
-            }
-        }
+        self.add_en_passant(&mut list);
 
         // Castling is only allowed when:
         // 1. We are not in check
@@ -1210,27 +3071,42 @@ impl Position {
     }
 }
 
+/// A move displayed in standard algebraic notation. Created by [Position::san]
+#[derive(Debug, Clone, Copy)]
+pub struct SanMove<'a>(&'a Position, Move);
+
+impl fmt::Display for SanMove<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.to_san(self.1))
+    }
+}
+
+/// Two positions are equal when they have the same pieces, side to move, castling rights and en
+/// passant square, no matter how they were reached
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.actual_state(), other.actual_state());
+        self.pieces_bb == other.pieces_bb
+            && a.turn == b.turn
+            && a.castling == b.castling
+            && a.ep == b.ep
+    }
+}
+
+impl Eq for Position {}
+
+/// Hashes the zobrist key of the position. Positions hashed together must use the same
+/// [ZobristKeys] for the hash to agree with the equality
+impl core::hash::Hash for Position {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        state.write_u64(Position::hash(self, true));
+    }
+}
+
+/// Prints the board. Use the alternate flag (`{:#}`) to print it with unicode chess figurines
 impl fmt::Display for Position {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut s = String::from("  +-----------------+\n");
-        for rank in (0..8).rev() {
-            s.push_str(&format!("{} | ", rank + 1));
-            for file in 0..8 {
-                if let Some(piece) = self.piece_on(rank * 8 + file) {
-                    let color = self.color_on(rank * 8 + file).unwrap();
-                    let mut chr = Piece::to_char(piece);
-                    if color == Color::WHITE {
-                        chr = chr.to_ascii_uppercase();
-                    }
-                    s.push_str(&format!("{chr} "));
-                } else {
-                    s.push_str(". ");
-                }
-            }
-            s.push_str("|\n");
-        }
-        s.push_str("  +-----------------+\n    a b c d e f g h");
-        write!(f, "{s}")
+        write!(f, "{}", self.render(f.alternate(), false, 0))
     }
 }
 
@@ -1238,71 +3114,28 @@ impl FromStr for Position {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut pos = Position::new();
-        let params = s.split(" ").collect::<Vec<&str>>();
-        let ranks = params[0].split("/").collect::<Vec<&str>>();
-
-        let mut sq = 0;
-
-        for rank in ranks.iter().rev() {
-            for piece in rank.chars() {
-                if piece.is_digit(10) {
-                    sq += piece.to_digit(10).unwrap();
-                    continue;
-                }
-                let color = if piece.is_uppercase() { 0 } else { 1 };
-                let piece_type = Piece::from_char(piece);
-                pos.pieces_bb[color][piece_type] |= 1u64 << sq;
-                sq += 1;
-            }
-        }
-        if pos.pieces_bb[Color::WHITE][Piece::KING] == 0
-            || pos.pieces_bb[Color::BLACK][Piece::KING] == 0
-        {
-            panic!("One king is missing")
-        }
-        pos.history[pos.ply].turn = if params[1] == "w" {
-            Color::WHITE
-        } else {
-            Color::BLACK
-        };
-
-        pos.history[pos.ply].castling = 0;
-        if params[2].contains("K") {
-            pos.history[pos.ply].castling |= State::WHITE_00
-        }
-        if params[2].contains("Q") {
-            pos.history[pos.ply].castling |= State::WHITE_000
-        }
-        if params[2].contains("k") {
-            pos.history[pos.ply].castling |= State::BLACK_00
-        }
-        if params[2].contains("q") {
-            pos.history[pos.ply].castling |= State::BLACK_000
-        }
-
-        if params[3] != "-" {
-            pos.history[pos.ply].ep = Some(Square::from_str(params[3]))
-        }
-
-        if params[4] != "-" {
-            pos.history[pos.ply].hm = params[4].parse::<usize>().unwrap()
-        }
-        if params[5] != "-" {
-            pos.history[pos.ply].fm = params[5].parse::<usize>().unwrap()
-        }
+        pos.set_fen(s)?;
+        Ok(pos)
+    }
+}
 
-        let (c, hv, d12) = pos.check_and_pin();
-        pos.checkmask = c;
-        pos.pin_hv = hv;
-        pos.pin_d12 = d12;
-        pos.danger = pos.attacks();
+#[cfg(feature = "serde")]
+impl serde::Serialize for Position {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.fen())
+    }
+}
 
-        Ok(pos)
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Position {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fen = String::deserialize(deserializer)?;
+        Position::from_str(&fen).map_err(serde::de::Error::custom)
     }
 }
 
 impl Default for Position {
     fn default() -> Self {
-        Self::from_str("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
+        Self::from_str(Self::START_FEN).unwrap()
     }
 }
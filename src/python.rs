@@ -0,0 +1,86 @@
+//! Python bindings, enabled with the `python` feature. They follow the basic surface of
+//! python-chess, with moves as UCI strings. Build the extension module with
+//! [maturin](https://www.maturin.rs), e.g. `maturin develop --features python,pyo3/extension-module`
+//!
+//! ```python
+//! import turbochess
+//!
+//! pos = turbochess.Position()  # Or turbochess.Position("<fen>")
+//! print(pos.legal())  # ['a2a3', 'b2b3', ...]
+//! pos.make("e2e4")
+//! print(pos.fen(), pos.perft(3))
+//! pos.undo()
+//! ```
+
+use alloc::{string::String, string::ToString, vec::Vec};
+use core::str::FromStr;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::Position;
+
+/// A position for Python
+#[pyclass(name = "Position")]
+pub struct PyPosition {
+    pos: Position,
+}
+
+#[pymethods]
+impl PyPosition {
+    /// Creates a position from a FEN, or the initial position without one. Raises `ValueError`
+    /// if the FEN is invalid
+    #[new]
+    #[pyo3(signature = (fen = None))]
+    pub fn new(fen: Option<&str>) -> PyResult<Self> {
+        let pos = match fen {
+            Some(fen) => Position::from_str(fen).map_err(PyValueError::new_err)?,
+            None => Position::default(),
+        };
+        Ok(Self { pos })
+    }
+    /// Gets the legal moves in UCI
+    pub fn legal(&self) -> Vec<String> {
+        self.pos
+            .legal()
+            .into_iter()
+            .map(|mv| mv.to_string())
+            .collect()
+    }
+    /// Makes a move given in UCI. Raises `ValueError` if it isn't legal
+    pub fn make(&mut self, uci: &str) -> PyResult<()> {
+        let mv = self
+            .pos
+            .legal()
+            .into_iter()
+            .find(|mv| mv.to_string() == uci)
+            .ok_or_else(|| PyValueError::new_err(alloc::format!("Illegal move '{uci}'")))?;
+        self.pos.make_move(mv);
+        Ok(())
+    }
+    /// Undoes the last move and returns it in UCI, or `None` if there are no moves
+    pub fn undo(&mut self) -> Option<String> {
+        self.pos.undo().map(|mv| mv.to_string())
+    }
+    /// Gets the FEN of the position
+    pub fn fen(&self) -> String {
+        self.pos.fen()
+    }
+    /// Checks if the side to move is checkmated
+    pub fn is_checkmate(&self) -> bool {
+        self.pos.is_checkmate()
+    }
+    /// Counts the leaf nodes of the move tree up to a depth
+    pub fn perft(&self, depth: usize) -> i64 {
+        crate::testing::perft(self.pos.clone(), depth)
+    }
+    fn __repr__(&self) -> String {
+        alloc::format!("Position('{}')", self.pos.fen())
+    }
+}
+
+/// The `turbochess` Python module
+#[pymodule]
+pub fn turbochess(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPosition>()
+}
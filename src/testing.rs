@@ -1,8 +1,18 @@
+use crate::types::Move;
+#[cfg(test)]
+use crate::types::{Color, Piece, Square, SquareChange};
 use crate::Position;
+use core::ops::AddAssign;
 use std::str::FromStr;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub fn perft(mut pos: Position, depth: usize) -> i64 {
+    perft_recursive(&mut pos, depth)
+}
+
+// The recursion makes and undoes the moves on a single position, instead of moving a copy of it
+// (and its history) into every call
+fn perft_recursive(pos: &mut Position, depth: usize) -> i64 {
     if depth == 0 {
         return 1;
     }
@@ -10,59 +20,254 @@ pub fn perft(mut pos: Position, depth: usize) -> i64 {
     let mut nodes = 0;
     for mv in moves {
         pos.make_move(mv);
-        nodes += perft(pos, depth - 1);
+        nodes += perft_recursive(pos, depth - 1);
         pos.undo_move(mv);
     }
     nodes
 }
 
-pub fn perft_complete(
-    mut pos: Position,
-    depth: usize,
-    cps: &mut usize,
-    eps: &mut usize,
-    cast: &mut usize,
-    proms: &mut usize,
-    checks: &mut usize,
-    mates: &mut usize,
-) -> i64 {
+/// Same as [perft], but generating the moves with [Position::legal_verified]. Comparing both
+/// helps finding the positions where the fast generator goes wrong
+pub fn perft_verified(mut pos: Position, depth: usize) -> i64 {
+    perft_verified_recursive(&mut pos, depth)
+}
+
+fn perft_verified_recursive(pos: &mut Position, depth: usize) -> i64 {
     if depth == 0 {
         return 1;
     }
-    let moves = pos.legal();
     let mut nodes = 0;
-    *cps += moves.count_captures();
-    *eps += moves.count_enpassants();
-    *cast += moves.count_castles();
-    *proms += moves.count_promotions();
-    if pos.in_check() {
-        *checks += 1;
-        if moves.clone().count() == 0 {
-            *mates += 1;
+    for mv in pos.legal_verified() {
+        pos.make_move(mv);
+        nodes += perft_verified_recursive(pos, depth - 1);
+        pos.undo_move(mv);
+    }
+    nodes
+}
+
+/// Breakdown of the leaf nodes of a perft, in the format used by the chessprogramming wiki
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PerftStats {
+    pub nodes: u64,
+    pub captures: u64,
+    pub en_passants: u64,
+    pub castles: u64,
+    pub promotions: u64,
+    pub checks: u64,
+    pub checkmates: u64,
+}
+
+impl AddAssign for PerftStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.nodes += rhs.nodes;
+        self.captures += rhs.captures;
+        self.en_passants += rhs.en_passants;
+        self.castles += rhs.castles;
+        self.promotions += rhs.promotions;
+        self.checks += rhs.checks;
+        self.checkmates += rhs.checkmates;
+    }
+}
+
+/// Counts the leaf nodes at a depth along with the kind of moves that reach them
+pub fn perft_stats(mut pos: Position, depth: usize) -> PerftStats {
+    perft_stats_recursive(&mut pos, depth)
+}
+
+fn perft_stats_recursive(pos: &mut Position, depth: usize) -> PerftStats {
+    let mut stats = PerftStats::default();
+    let moves = pos.legal();
+    if depth == 0 {
+        stats.nodes = 1;
+        if pos.in_check() {
+            stats.checks = 1;
+            stats.checkmates = (moves.count() == 0) as u64;
         }
+        return stats;
+    }
+    if depth == 1 {
+        // En passants are captures too
+        stats.captures += (moves.count_captures() + moves.count_enpassants()) as u64;
+        stats.en_passants += moves.count_enpassants() as u64;
+        stats.castles += moves.count_castles() as u64;
+        stats.promotions += moves.count_promotions() as u64;
     }
     for mv in moves {
         pos.make_move(mv);
-        nodes += perft_complete(pos, depth - 1, cps, eps, cast, proms, checks, mates);
+        stats += perft_stats_recursive(pos, depth - 1);
         pos.undo_move(mv);
     }
-    nodes
+    stats
 }
 
-pub fn perft_divide(mut pos: Position, depth: usize) -> i64 {
+/// Counts the leaf nodes under every root move, sorted by the moves in UCI notation
+pub fn perft_divide(mut pos: Position, depth: usize) -> Vec<(Move, i64)> {
+    let mut divide = Vec::new();
     if depth == 0 {
-        return 1;
+        return divide;
     }
-    let moves = pos.legal();
-    let mut nodes = 0;
-    for mv in moves {
+    for mv in pos.legal() {
         pos.make_move(mv);
-        let res = perft(pos, depth - 1);
-        println!("{mv}: {res}");
-        nodes += res;
+        divide.push((mv, perft_recursive(&mut pos, depth - 1)));
         pos.undo_move(mv);
     }
-    nodes
+    divide.sort_by_cached_key(|(mv, _)| mv.to_string());
+    divide
+}
+
+/// Positions and depths used by default in a benchmark
+pub const BENCH_SUITE: &[(&str, usize)] = &[
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        5,
+    ),
+    (
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        4,
+    ),
+];
+
+/// Result of a benchmark
+#[derive(Debug, Clone, Copy)]
+pub struct BenchReport {
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    /// Gets the nodes searched per second
+    pub fn nps(&self) -> f64 {
+        self.nodes as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Runs a perft over every position of a suite and times it. Panics if a fen is invalid
+pub fn bench(positions: &[(&str, usize)]) -> BenchReport {
+    let mut report = BenchReport {
+        nodes: 0,
+        elapsed: Duration::ZERO,
+    };
+    for &(fen, depth) in positions {
+        let pos = Position::from_str(fen).unwrap();
+        let start = Instant::now();
+        report.nodes += perft(pos, depth) as u64;
+        report.elapsed += start.elapsed();
+    }
+    report
+}
+
+/// Generates a random position to fuzz the move generator with. Both kings are on the board and
+/// not next to each other, the pawns aren't on the first or last rank, the side not to move
+/// isn't in check, and the castling rights and en passant square match the pieces
+#[cfg(feature = "rand")]
+pub fn random_position<R: rand::Rng + ?Sized>(rng: &mut R) -> Position {
+    use crate::types::{BitBoard, Color, Piece, Square};
+    use crate::State;
+
+    loop {
+        let mut pos = Position::empty();
+        let white_king = rng.gen_range(0..64);
+        let black_king = loop {
+            let sq = rng.gen_range(0..64);
+            if Square::distance(sq, white_king) > 1 {
+                break sq;
+            }
+        };
+        pos.add_piece(white_king, Piece::KING, Color::WHITE);
+        pos.add_piece(black_king, Piece::KING, Color::BLACK);
+        for color in [Color::WHITE, Color::BLACK] {
+            for (piece, max) in [
+                (Piece::PAWN, 8),
+                (Piece::KNIGHT, 2),
+                (Piece::BISHOP, 2),
+                (Piece::ROOK, 2),
+                (Piece::QUEEN, 1),
+            ] {
+                for _ in 0..rng.gen_range(0..=max) {
+                    let sq = rng.gen_range(0..64);
+                    let rank = Square::rank(sq);
+                    if pos.piece_on(sq).is_none()
+                        && (piece != Piece::PAWN || rank != 0 && rank != 7)
+                    {
+                        pos.add_piece(sq, piece, color);
+                    }
+                }
+            }
+        }
+        let turn = rng.gen_range(0..2);
+        pos.set_turn(turn);
+        if pos.is_in_check(1 - turn) {
+            continue;
+        }
+
+        let mut castling = 0;
+        for color in [Color::WHITE, Color::BLACK] {
+            if pos.bb_of(color, Piece::KING) != 1u64 << State::KING_START[color] {
+                continue;
+            }
+            for (side, rook) in [
+                (State::SHORT[color], State::SHORT_ROOK[color]),
+                (State::LONG[color], State::LONG_ROOK[color]),
+            ] {
+                if pos.bb_of(color, Piece::ROOK) & (1u64 << rook) != 0 && rng.gen_bool(0.5) {
+                    castling |= side;
+                }
+            }
+        }
+        pos.set_castling_rights(castling);
+
+        // The opponent may have just pushed a pawn two squares
+        let them = 1 - turn;
+        let occ = pos.occupancy();
+        let pushed =
+            BitBoard::squares(pos.bb_of(them, Piece::PAWN) & BitBoard::relative_rank(4, them))
+                .filter(|&sq| {
+                    let (passed, start) = if them == Color::WHITE {
+                        (sq - 8, sq - 16)
+                    } else {
+                        (sq + 8, sq + 16)
+                    };
+                    occ & (1u64 << passed | 1u64 << start) == 0
+                })
+                .collect::<Vec<_>>();
+        if !pushed.is_empty() && rng.gen_bool(0.5) {
+            let sq = pushed[rng.gen_range(0..pushed.len())];
+            pos.set_en_passant(Some(if them == Color::WHITE { sq - 8 } else { sq + 8 }));
+        }
+        return pos;
+    }
+}
+
+/// Checks [Position::legal] against [Position::legal_verified] in random positions and in the
+/// positions a few random moves away from them. Panics with the FEN and the moves only one of them
+/// generates on the first difference
+#[cfg(feature = "rand")]
+pub fn fuzz_movegen(iterations: usize, seed: u64) {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let mut rng = SmallRng::seed_from_u64(seed);
+    for _ in 0..iterations {
+        let mut pos = random_position(&mut rng);
+        for _ in 0..4 {
+            let legal = pos.legal();
+            let verified = pos.legal_verified();
+            let missing = verified.into_iter().filter(|&mv| !legal.contains(mv));
+            let extra = legal.into_iter().filter(|&mv| !verified.contains(mv));
+            let missing = missing.map(|mv| mv.to_string()).collect::<Vec<_>>();
+            let extra = extra.map(|mv| mv.to_string()).collect::<Vec<_>>();
+            assert!(
+                missing.is_empty() && extra.is_empty(),
+                "Move generation differs in {}\nMissing: {}\nExtra: {}",
+                pos.fen(),
+                missing.join(" "),
+                extra.join(" ")
+            );
+            match pos.random_legal(&mut rng) {
+                Some(mv) => pos.make_move(mv),
+                None => break,
+            };
+        }
+    }
 }
 
 macro_rules! test_perft {
@@ -102,7 +307,11 @@ fn perft_test() {
 
     let depth = 4;
     let start = Instant::now();
-    let nodes = perft_divide(pos, depth);
+    let mut nodes = 0;
+    for (mv, res) in perft_divide(pos, depth) {
+        println!("{mv}: {res}");
+        nodes += res;
+    }
     let duration = start.elapsed();
     let nps = nodes as f64 / duration.as_secs_f64();
     println!(
@@ -161,3 +370,2549 @@ fn perft_tests() {
         89941194
     );
 }
+
+#[test]
+fn hash_side_to_move() {
+    let white = Position::from_str("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+    let black = Position::from_str("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+    assert_ne!(white.hash(true), black.hash(true));
+}
+
+#[test]
+fn hash_set_unset_square() {
+    let mut pos = Position::default();
+    let hash = pos.hash(true);
+
+    // Clearing an empty square mustn't touch the hash
+    pos.unset_square(Square::E4);
+    assert_eq!(pos.hash(true), hash);
+
+    pos.set_square(Square::E4, Piece::KNIGHT, Color::WHITE);
+    pos.set_square(Square::E4, Piece::QUEEN, Color::BLACK);
+    pos.unset_square(Square::E4);
+    assert_eq!(pos.hash(true), hash);
+}
+
+#[test]
+fn attackers_from_king() {
+    let pos = Position::from_str("8/8/8/3k4/4P3/8/8/4K3 w - - 0 1").unwrap();
+    let attackers = pos.attackers_from(Square::E4, Color::BLACK, pos.occupancy());
+    assert_eq!(attackers, 1u64 << Square::D5);
+}
+
+#[test]
+fn fen_errors() {
+    assert!(Position::from_str("").is_err());
+    assert!(Position::from_str("8/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    assert!(Position::from_str("4k3/8/8/8/8/8/8/4X3 w - - 0 1").is_err());
+    assert!(Position::from_str("4k3/8/8/8/8/8/8/4K3 x - - 0 1").is_err());
+    assert!(Position::from_str("4k3/8/8/8/8/8/8/4K3 w - z9 0 1").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_position() {
+    for fen in [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w Kq f6 0 3",
+    ] {
+        let pos = Position::from_str(fen).unwrap();
+        let json = serde_json::to_string(&pos).unwrap();
+        assert_eq!(json, format!("\"{fen}\""));
+        let back: Position = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.fen(), fen);
+    }
+    assert!(serde_json::from_str::<Position>("\"not a fen\"").is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_moves() {
+    use crate::types::{Move, MoveList};
+
+    let promotion = Move::new(Square::E7, Square::E8, Move::PR_Q);
+    let json = serde_json::to_string(&promotion).unwrap();
+    assert_eq!(json, "\"e7e8q\"");
+    assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), promotion);
+
+    // Castles have no board context, so they come back as quiet king moves
+    let castle = Move::new(Square::E1, Square::G1, Move::CASTLE_00);
+    let json = serde_json::to_string(&castle).unwrap();
+    assert_eq!(json, "\"e1g1\"");
+    let back = serde_json::from_str::<Move>(&json).unwrap();
+    assert_eq!(back.to_string(), castle.to_string());
+    assert_eq!(back.flag(), Move::QUIET);
+
+    let mut list = MoveList::new();
+    list.add_raw(promotion);
+    list.add_raw(castle);
+    let json = serde_json::to_string(&list).unwrap();
+    assert_eq!(json, "[\"e7e8q\",\"e1g1\"]");
+    let back = serde_json::from_str::<MoveList>(&json).unwrap();
+    assert_eq!(back.count(), 2);
+
+    assert!(serde_json::from_str::<Move>("\"e9e4\"").is_err());
+}
+
+#[test]
+fn hash_seeded_keys() {
+    use crate::ZobristKeys;
+
+    static KEYS_A: ZobristKeys = ZobristKeys::from_seed(1);
+    static KEYS_B: ZobristKeys = ZobristKeys::from_seed(1);
+    static KEYS_C: ZobristKeys = ZobristKeys::from_seed(2);
+
+    let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    let a = Position::from_str(fen).unwrap().with_keys(&KEYS_A);
+    let b = Position::from_str(fen).unwrap().with_keys(&KEYS_B);
+    let c = Position::from_str(fen).unwrap().with_keys(&KEYS_C);
+    assert_eq!(a.hash(true), b.hash(true));
+    assert_ne!(a.hash(true), c.hash(true));
+    assert_ne!(a.hash(true), Position::from_str(fen).unwrap().hash(true));
+}
+
+#[test]
+fn hash_pawns() {
+    let mut pos = Position::default();
+    let pawn_hash = pos.pawn_hash();
+    pos.make_move(Move::new(Square::G1, Square::F3, Move::QUIET));
+    assert_eq!(pos.pawn_hash(), pawn_hash);
+    pos.make_move(Move::new(Square::E7, Square::E5, Move::DOUBLE_PUSH));
+    assert_ne!(pos.pawn_hash(), pawn_hash);
+}
+
+#[test]
+fn display_unicode() {
+    let pos = Position::default();
+    assert_eq!(
+        pos.to_unicode_string(),
+        "  +-----------------+
+8 | ♜ ♞ ♝ ♛ ♚ ♝ ♞ ♜ |
+7 | ♟ ♟ ♟ ♟ ♟ ♟ ♟ ♟ |
+6 | · · · · · · · · |
+5 | · · · · · · · · |
+4 | · · · · · · · · |
+3 | · · · · · · · · |
+2 | ♙ ♙ ♙ ♙ ♙ ♙ ♙ ♙ |
+1 | ♖ ♘ ♗ ♕ ♔ ♗ ♘ ♖ |
+  +-----------------+
+    a b c d e f g h"
+    );
+    assert_eq!(format!("{pos:#}"), pos.to_unicode_string());
+    assert!(pos.to_string().contains("8 | r n b q k b n r |"));
+}
+
+#[test]
+fn display_flipped() {
+    let pos = Position::from_str("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+    let flipped = pos.to_string_flipped();
+    assert_eq!(
+        flipped,
+        "  +-----------------+
+1 | . . . K . . . R |
+2 | . . . . . . . . |
+3 | . . . . . . . . |
+4 | . . . . . . . . |
+5 | . . . . . . . . |
+6 | . . . . . . . . |
+7 | . . . . . . . . |
+8 | . . . k . . . . |
+  +-----------------+
+    h g f e d c b a"
+    );
+
+    // Flipping the flipped board back gives the normal view
+    let normal = pos.to_string();
+    let rows = |s: &str| -> Vec<String> {
+        s.lines()
+            .filter(|l| l.contains('|'))
+            .map(|l| l[4..19].to_string())
+            .collect()
+    };
+    let flipped_back = rows(&flipped)
+        .iter()
+        .rev()
+        .map(|r| r.chars().rev().collect::<String>())
+        .collect::<Vec<String>>();
+    assert_eq!(flipped_back, rows(&normal));
+}
+
+#[test]
+fn san_format() {
+    let pos = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let castle = Move::new(Square::E1, Square::G1, Move::CASTLE_00);
+    assert_eq!(format!("{}", pos.san(castle)), "O-O");
+    let castle = Move::new(Square::E1, Square::C1, Move::CASTLE_000);
+    assert_eq!(format!("{}", pos.san(castle)), "O-O-O");
+    let check = Move::new(Square::A1, Square::A8, Move::CAPTURE);
+    assert_eq!(format!("{}", pos.san(check)), "Rxa8+");
+
+    // Both rooks can go to d1
+    let pos = Position::from_str("4k3/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+    assert_eq!(
+        pos.to_san(Move::new(Square::A1, Square::D1, Move::QUIET)),
+        "Rad1"
+    );
+
+    // Scholar's mate
+    let pos =
+        Position::from_str("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3")
+            .unwrap();
+    assert_eq!(
+        pos.to_san(Move::new(Square::F3, Square::F7, Move::CAPTURE)),
+        "Qxf7#"
+    );
+}
+
+#[test]
+fn no_std_build() {
+    // Builds the crate without the std feature in its own target directory
+    let output = std::process::Command::new(env!("CARGO"))
+        .args(["build", "--no-default-features"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .env(
+            "CARGO_TARGET_DIR",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/target/no_std"),
+        )
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn build_position() {
+    let mut pos = Position::empty();
+    pos.set_piece(Square::E1, Piece::KING, Color::WHITE);
+    pos.set_piece(Square::E8, Piece::KING, Color::BLACK);
+    pos.set_piece(Square::D4, Piece::QUEEN, Color::BLACK);
+    pos.set_piece(Square::D4, Piece::KNIGHT, Color::WHITE);
+    pos.set_piece(Square::H7, Piece::PAWN, Color::BLACK);
+    pos.remove_piece(Square::H7);
+    pos.remove_piece(Square::H6);
+    pos.update_checks();
+
+    let expected = Position::from_str("4k3/8/8/8/3N4/8/8/4K3 w - - 0 1").unwrap();
+    assert_eq!(pos.fen(), expected.fen());
+    assert_eq!(pos.hash(true), expected.hash(true));
+    assert_eq!(pos.legal().count(), expected.legal().count());
+}
+
+#[test]
+fn hash_add_remove_pieces() {
+    let mut pos = Position::from_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    pos.add_piece(Square::D4, Piece::KNIGHT, Color::WHITE);
+    pos.add_piece(Square::H7, Piece::PAWN, Color::BLACK);
+    pos.remove_piece(Square::D4);
+    pos.add_piece(Square::D4, Piece::QUEEN, Color::BLACK);
+    pos.set_piece(Square::H7, Piece::ROOK, Color::WHITE);
+    pos.remove_piece(Square::A1);
+    pos.move_quiet(Square::E1, Square::F2);
+    assert_eq!(pos.hash, pos.compute_hash());
+}
+
+#[test]
+fn null_move() {
+    let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+    let mut pos = Position::from_str(fen).unwrap();
+    let hash = pos.hash(true);
+    pos.make_null_move();
+    assert_eq!(
+        pos.fen(),
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR b KQkq - 1 3"
+    );
+    assert_ne!(pos.hash(true), hash);
+    pos.undo_null_move();
+    assert_eq!(pos.fen(), fen);
+    assert_eq!(pos.hash(true), hash);
+}
+
+#[test]
+fn make_move_captured() {
+    let mut pos = Position::from_str("4k2r/8/8/8/8/8/8/4K2R w - - 0 1").unwrap();
+    assert_eq!(
+        pos.make_move(Move::new(Square::E1, Square::D2, Move::QUIET)),
+        None
+    );
+    pos.undo_move(Move::new(Square::E1, Square::D2, Move::QUIET));
+    let capture = Move::new(Square::H1, Square::H8, Move::CAPTURE);
+    assert_eq!(pos.make_move(capture), Some(Piece::ROOK));
+    pos.undo_move(capture);
+    assert_eq!(pos.fen(), "4k2r/8/8/8/8/8/8/4K2R w - - 0 1");
+
+    let mut pos = Position::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+    let ep = Move::new(Square::E5, Square::D6, Move::EN_PASSANT);
+    assert_eq!(pos.make_move(ep), Some(Piece::PAWN));
+    pos.undo_move(ep);
+    assert_eq!(pos.fen(), "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+}
+
+#[test]
+fn try_make_move() {
+    use crate::types::{Move, MoveError};
+
+    let mut pos = Position::default();
+    let fen = pos.fen();
+    assert_eq!(
+        pos.try_make_move(Move::new(Square::E4, Square::E5, Move::QUIET)),
+        Err(MoveError::EmptySquare)
+    );
+    assert_eq!(
+        pos.try_make_move(Move::new(Square::E7, Square::E5, Move::DOUBLE_PUSH)),
+        Err(MoveError::NotYourPiece)
+    );
+    assert_eq!(
+        pos.try_make_move(Move::new(Square::E1, Square::E2, Move::QUIET)),
+        Err(MoveError::Illegal)
+    );
+    // Right squares but wrong flag
+    assert_eq!(
+        pos.try_make_move(Move::new(Square::E2, Square::E4, Move::QUIET)),
+        Err(MoveError::Illegal)
+    );
+    assert_eq!(pos.fen(), fen);
+    assert_eq!(
+        pos.try_make_move(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)),
+        Ok(())
+    );
+}
+
+#[test]
+fn move_from_squares() {
+    let pos = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(
+        pos.move_from_squares(Square::E1, Square::G1, None),
+        Some(Move::new(Square::E1, Square::G1, Move::CASTLE_00))
+    );
+    assert_eq!(
+        pos.move_from_squares(Square::E1, Square::C1, None),
+        Some(Move::new(Square::E1, Square::C1, Move::CASTLE_000))
+    );
+    assert_eq!(
+        pos.move_from_squares(Square::A1, Square::A8, None),
+        Some(Move::new(Square::A1, Square::A8, Move::CAPTURE))
+    );
+    assert_eq!(pos.move_from_squares(Square::A1, Square::B2, None), None);
+
+    let pos = Position::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+    assert_eq!(
+        pos.move_from_squares(Square::E5, Square::D6, None),
+        Some(Move::new(Square::E5, Square::D6, Move::EN_PASSANT))
+    );
+
+    let pos = Position::from_str("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    assert_eq!(
+        pos.move_from_squares(Square::A7, Square::B8, Some(Piece::KNIGHT)),
+        Some(Move::new(Square::A7, Square::B8, Move::PC_N))
+    );
+    assert_eq!(
+        pos.move_from_squares(Square::A7, Square::A8, Some(Piece::QUEEN)),
+        Some(Move::new(Square::A7, Square::A8, Move::PR_Q))
+    );
+    assert_eq!(pos.move_from_squares(Square::A7, Square::A8, None), None);
+}
+
+#[test]
+fn least_valuable_attacker() {
+    // d5 is attacked by a pawn, a knight, a rook and a queen
+    let pos = Position::from_str("4k3/8/8/3p4/4P3/2N5/8/3RK2Q w - - 0 1").unwrap();
+    let occ = pos.occupancy();
+    assert_eq!(
+        pos.least_valuable_attacker(Square::D5, Color::WHITE, occ),
+        Some((Square::E4, Piece::PAWN))
+    );
+    let occ = occ & !(1u64 << Square::E4);
+    assert_eq!(
+        pos.least_valuable_attacker(Square::D5, Color::WHITE, occ),
+        Some((Square::C3, Piece::KNIGHT))
+    );
+    let occ = occ & !(1u64 << Square::C3);
+    assert_eq!(
+        pos.least_valuable_attacker(Square::D5, Color::WHITE, occ),
+        Some((Square::D1, Piece::ROOK))
+    );
+    let occ = occ & !(1u64 << Square::D1);
+    assert_eq!(
+        pos.least_valuable_attacker(Square::D5, Color::WHITE, occ),
+        Some((Square::H1, Piece::QUEEN))
+    );
+    assert_eq!(
+        pos.least_valuable_attacker(Square::D5, Color::BLACK, occ),
+        None
+    );
+}
+
+#[test]
+fn mobility() {
+    let pos = Position::default();
+    assert_eq!(pos.mobility(Color::WHITE), 20);
+    assert_eq!(pos.mobility(Color::BLACK), 20);
+
+    // Rook: 10, king: 5, pawn: a push and a capture
+    let pos = Position::from_str("4k3/8/8/8/3p4/4P3/8/R3K3 b - - 0 1").unwrap();
+    assert_eq!(pos.mobility(Color::WHITE), 10 + 5 + 2);
+}
+
+#[test]
+fn passed_pawns() {
+    // The a5 pawn is passed, c4 is guarded by the d6 pawn and g4 is blocked by g5
+    let pos = Position::from_str("4k3/8/3p4/P5p1/2P3P1/8/8/4K3 w - - 0 1").unwrap();
+    assert!(pos.is_passed_pawn(Square::A5, Color::WHITE));
+    assert!(!pos.is_passed_pawn(Square::C4, Color::WHITE));
+    assert!(!pos.is_passed_pawn(Square::G4, Color::WHITE));
+    assert_eq!(pos.passed_pawns(Color::WHITE), 1u64 << Square::A5);
+    assert_eq!(pos.passed_pawns(Color::BLACK), 0);
+}
+
+#[test]
+fn pawn_structure() {
+    // Doubled and isolated c-pawns, a doubled f-pawn supported by the g-pawn and an isolated a-pawn
+    let pos = Position::from_str("4k3/8/8/2P2P2/P1P2PP1/8/8/4K3 w - - 0 1").unwrap();
+    let c_pawns = (1u64 << Square::C4) | (1u64 << Square::C5);
+    let f_pawns = (1u64 << Square::F4) | (1u64 << Square::F5);
+    assert_eq!(pos.doubled_pawns(Color::WHITE), c_pawns | f_pawns);
+    assert_eq!(
+        pos.isolated_pawns(Color::WHITE),
+        c_pawns | (1u64 << Square::A4)
+    );
+    assert_eq!(pos.doubled_pawns(Color::BLACK), 0);
+    assert_eq!(pos.isolated_pawns(Color::BLACK), 0);
+}
+
+#[test]
+fn attack_map() {
+    let pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    assert_eq!(pos.attack_map(Color::BLACK), pos.danger);
+
+    // The rook attacks through the enemy king
+    let pos = Position::from_str("R3k3/8/8/8/8/8/8/4K3 b - - 0 1").unwrap();
+    assert_eq!(pos.attack_map(Color::WHITE), pos.danger);
+    assert_ne!(pos.attack_map(Color::WHITE) & (1u64 << Square::H8), 0);
+    assert_eq!(
+        pos.attack_map(Color::BLACK),
+        crate::lookup::KING_MASK[Square::E8]
+    );
+}
+
+#[test]
+fn movelist_borrow() {
+    let list = Position::default().legal();
+    assert_eq!(list.as_slice().len(), 20);
+    let mut first = 0;
+    for mv in &list {
+        assert!(mv.from() < 64);
+        first += 1;
+    }
+    let second = (&list).into_iter().count();
+    assert_eq!(first, 20);
+    assert_eq!(second, 20);
+    assert_eq!(list.as_slice(), list.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn movelist_mvv_lva() {
+    // The knight can take a pawn or the queen, the pawn can take the knight
+    let pos = Position::from_str("4k3/8/2p5/3q4/1N6/p7/1P6/4K3 w - - 0 1").unwrap();
+    let mut list = pos.legal();
+    let quiets = list
+        .into_iter()
+        .filter(|mv| !mv.is_capture())
+        .collect::<Vec<_>>();
+    list.sort_mvv_lva(&pos);
+    assert_eq!(
+        list.as_slice()[..3],
+        [
+            Move::new(Square::B4, Square::D5, Move::CAPTURE),
+            Move::new(Square::B2, Square::A3, Move::CAPTURE),
+            Move::new(Square::B4, Square::C6, Move::CAPTURE),
+        ]
+    );
+    assert_eq!(list.as_slice()[3..], quiets);
+}
+
+#[test]
+fn movelist_find() {
+    let pos = Position::from_str("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let list = pos.legal();
+    assert!(list.contains(Move::new(Square::E1, Square::E2, Move::QUIET)));
+    assert!(!list.contains(Move::new(Square::E1, Square::E3, Move::QUIET)));
+    assert_eq!(
+        list.find_from_to(Square::E1, Square::D2, None),
+        Some(Move::new(Square::E1, Square::D2, Move::QUIET))
+    );
+    assert_eq!(list.find_from_to(Square::E1, Square::D3, None), None);
+
+    // Both pawn moves are ambiguous without the promotion piece
+    assert_eq!(list.find_from_to(Square::A7, Square::A8, None), None);
+    assert_eq!(
+        list.find_from_to(Square::A7, Square::A8, Some(Piece::ROOK)),
+        Some(Move::new(Square::A7, Square::A8, Move::PR_R))
+    );
+    assert_eq!(
+        list.find_from_to(Square::A7, Square::B8, Some(Piece::KNIGHT)),
+        Some(Move::new(Square::A7, Square::B8, Move::PC_N))
+    );
+    assert_eq!(
+        list.find_from_to(Square::A7, Square::B8, Some(Piece::KING)),
+        None
+    );
+}
+
+#[test]
+fn movelist_vec() {
+    use crate::types::{Move, MoveList};
+
+    let list = Position::default().legal();
+    let moves = list.to_vec();
+    assert_eq!(moves.len(), 20);
+    assert_eq!(moves, list.as_slice());
+    let rebuilt = moves.into_iter().collect::<MoveList>();
+    assert_eq!(rebuilt.as_slice(), list.as_slice());
+
+    let result =
+        std::panic::catch_unwind(|| core::iter::repeat_n(Move::EMPTY, 219).collect::<MoveList>());
+    assert!(result.is_err());
+}
+
+#[test]
+fn bitboard_squares() {
+    use crate::types::BitBoard;
+
+    let bb = 1u64 << Square::B2 | 1u64 << Square::E4 | 1u64 << Square::H8;
+    assert_eq!(BitBoard::squares(bb).len(), 3);
+    assert_eq!(
+        BitBoard::squares(bb).collect::<Vec<_>>(),
+        [Square::B2, Square::E4, Square::H8]
+    );
+    assert_eq!(BitBoard::squares(0).next(), None);
+    assert!(BitBoard::squares(u64::MAX).eq(Square::iter()));
+}
+
+#[test]
+fn perft_kiwipete_stats() {
+    let pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let expected = [(48, 8, 0, 2, 0, 0, 0), (2039, 351, 1, 91, 0, 3, 0)];
+    for (depth, e) in expected.into_iter().enumerate() {
+        let stats = perft_stats(pos.clone(), depth + 1);
+        assert_eq!(
+            stats,
+            PerftStats {
+                nodes: e.0,
+                captures: e.1,
+                en_passants: e.2,
+                castles: e.3,
+                promotions: e.4,
+                checks: e.5,
+                checkmates: e.6,
+            }
+        );
+    }
+}
+
+#[test]
+fn perft_divide_sum() {
+    let pos = Position::default();
+    let divide = perft_divide(pos.clone(), 3);
+    assert_eq!(divide.len(), 20);
+    assert_eq!(divide.iter().map(|&(_, n)| n).sum::<i64>(), perft(pos, 3));
+    assert_eq!(divide[0].0.to_string(), "a2a3");
+    assert!(divide
+        .windows(2)
+        .all(|w| w[0].0.to_string() < w[1].0.to_string()));
+}
+
+#[test]
+fn bench_report() {
+    let report = bench(&[(BENCH_SUITE[0].0, 2), (BENCH_SUITE[1].0, 1)]);
+    assert_eq!(report.nodes, 400 + 48);
+    assert!(report.nps() > 0.);
+}
+
+#[test]
+fn array_round_trip() {
+    use crate::State;
+
+    let pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 0 1")
+            .unwrap();
+    let array = pos.to_array();
+    assert_eq!(array[Square::E1], Some((Piece::KING, Color::WHITE)));
+    assert_eq!(array[Square::E7], Some((Piece::QUEEN, Color::BLACK)));
+    assert_eq!(array[Square::E2], Some((Piece::BISHOP, Color::WHITE)));
+    assert_eq!(array[Square::E3], None);
+    let copy = Position::from_array(
+        array,
+        Color::BLACK,
+        State::WHITE_00 | State::BLACK_000,
+        None,
+    );
+    assert_eq!(copy.fen(), pos.fen());
+    assert_eq!(copy.hash(true), pos.hash(true));
+    assert_eq!(copy.legal().as_slice(), pos.legal().as_slice());
+}
+
+#[test]
+fn flip_colors() {
+    let fens = [
+        (
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            2,
+        ),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 2),
+        (
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            2,
+        ),
+    ];
+    for (fen, depth) in fens {
+        let pos = Position::from_str(fen).unwrap();
+        let flipped = pos.flip_colors();
+        assert_eq!(flipped.flip_colors().fen(), pos.fen());
+        assert_eq!(perft(flipped, depth), perft(pos, depth));
+    }
+    let flipped = Position::from_str(fens[2].0).unwrap().flip_colors();
+    assert_eq!(
+        flipped.fen(),
+        "rnbqkbnr/pppp1ppp/8/8/3PpP2/8/PPP1P1PP/RNBQKBNR b KQkq f3 0 3"
+    );
+}
+
+#[test]
+fn rotate180() {
+    let pos = Position::from_str("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 3 10").unwrap();
+    let rotated = pos.rotate180();
+    assert_eq!(rotated.fen(), "8/1P1P4/8/k1p3R1/r5PK/4p3/5p2/8 w - - 3 10");
+    assert_eq!(rotated.rotate180().fen(), pos.fen());
+    assert_eq!(rotated.rotate180().hash(true), pos.hash(true));
+
+    let rotated = Position::default().rotate180();
+    assert_eq!(
+        rotated.fen(),
+        "RNBKQBNR/PPPPPPPP/8/8/8/8/pppppppp/rnbkqbnr w - - 0 1"
+    );
+}
+
+#[test]
+fn move_predicates() {
+    let expected = [
+        (Move::QUIET, None, false, false, false),
+        (Move::CAPTURE, None, false, false, false),
+        (Move::DOUBLE_PUSH, None, false, false, false),
+        (Move::EN_PASSANT, None, false, false, true),
+        (Move::CASTLE_00, None, false, true, false),
+        (Move::CASTLE_000, None, false, true, false),
+        (Move::PR_N, Some(Piece::KNIGHT), true, false, false),
+        (Move::PR_B, Some(Piece::BISHOP), true, false, false),
+        (Move::PR_R, Some(Piece::ROOK), true, false, false),
+        (Move::PR_Q, Some(Piece::QUEEN), true, false, false),
+        (Move::PC_N, Some(Piece::KNIGHT), true, false, false),
+        (Move::PC_B, Some(Piece::BISHOP), true, false, false),
+        (Move::PC_R, Some(Piece::ROOK), true, false, false),
+        (Move::PC_Q, Some(Piece::QUEEN), true, false, false),
+    ];
+    for (flag, piece, promotion, castle, en_passant) in expected {
+        let mv = Move::new(Square::B7, Square::B8, flag);
+        assert_eq!(mv.promotion_piece(), piece);
+        assert_eq!(mv.is_promotion(), promotion);
+        assert_eq!(mv.is_castle(), castle);
+        assert_eq!(mv.is_en_passant(), en_passant);
+    }
+}
+
+#[test]
+fn movelist_count_with_a1a1() {
+    use crate::types::MoveList;
+
+    let mut list = MoveList::new();
+    list.add(Square::A1, Square::A1, Move::QUIET);
+    list.add(Square::B7, Square::A8, Move::PC_Q);
+    list.add(Square::E5, Square::D6, Move::EN_PASSANT);
+    list.add(Square::E1, Square::G1, Move::CASTLE_00);
+    list.add(Square::E4, Square::D5, Move::CAPTURE);
+    assert_eq!(list.count_promotions(), 1);
+    assert_eq!(list.count_captures(), 2);
+    assert_eq!(list.count_enpassants(), 1);
+    assert_eq!(list.count_castles(), 1);
+
+    // Moves dropped by a filter aren't counted anymore
+    list.filter_to(Square::A8);
+    assert_eq!(list.count_castles(), 0);
+    assert_eq!(list.count_captures(), 1);
+
+    assert!(Move::NULL.is_null());
+    assert_ne!(Move::NULL, Move::EMPTY);
+    assert_eq!(Move::NULL.to_string(), "0000");
+    assert!(!Position::default().legal().contains(Move::NULL));
+}
+
+#[test]
+fn hash_after() {
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+    ];
+    for fen in fens {
+        let mut pos = Position::from_str(fen).unwrap();
+        for mv in pos.legal() {
+            let hash = pos.hash_after(mv);
+            pos.make_move(mv);
+            assert_eq!(pos.hash(true), hash, "{fen} {mv}");
+            for reply in pos.legal() {
+                let hash = pos.hash_after(reply);
+                pos.make_move(reply);
+                assert_eq!(pos.hash(true), hash, "{fen} {mv} {reply}");
+                pos.undo_move(reply);
+            }
+            pos.undo_move(mv);
+        }
+    }
+}
+
+#[test]
+fn repetition_count() {
+    let mut pos = Position::default();
+    let moves = [
+        Move::new(Square::G1, Square::F3, Move::QUIET),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::F3, Square::G1, Move::QUIET),
+        Move::new(Square::F6, Square::G8, Move::QUIET),
+    ];
+    assert_eq!(pos.repetition_count(), 1);
+    for mv in &moves[..3] {
+        pos.make_move(*mv);
+        assert_eq!(pos.repetition_count(), 1);
+    }
+    pos.make_move(moves[3]);
+    assert_eq!(pos.repetition_count(), 2);
+    assert!(pos.is_repetition());
+    for mv in moves {
+        pos.make_move(mv);
+    }
+    assert_eq!(pos.repetition_count(), 3);
+    pos.undo_move(moves[3]);
+    assert_eq!(pos.repetition_count(), 2);
+
+    // A pawn move resets the count
+    pos.make_move(Move::new(Square::E7, Square::E6, Move::QUIET));
+    assert_eq!(pos.repetition_count(), 1);
+    assert!(!pos.is_repetition());
+}
+
+#[test]
+fn set_state() {
+    use crate::State;
+
+    let mut pos = Position::default();
+    let hash = pos.hash(true);
+    pos.set_castling_rights(State::WHITE_000);
+    assert_eq!(
+        pos.fen(),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Q - 0 1"
+    );
+    assert_ne!(pos.hash(true), hash);
+    pos.set_castling_rights(State::ALL_CASTLING);
+    assert_eq!(pos.hash(true), hash);
+
+    let fen = "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3";
+    let mut pos = Position::from_str(fen).unwrap();
+    let hash = pos.hash(true);
+    assert_eq!(pos.legal().count_enpassants(), 1);
+    pos.set_en_passant(None);
+    assert_eq!(pos.legal().count_enpassants(), 0);
+    assert_eq!(
+        pos.fen(),
+        "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3"
+    );
+    pos.set_en_passant(Some(Square::E3));
+    assert_eq!(pos.fen(), fen);
+    assert_eq!(pos.hash(true), hash);
+
+    pos.set_turn(Color::WHITE);
+    pos.set_en_passant(None);
+    assert_eq!(
+        pos.hash(true),
+        Position::from_str("rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3")
+            .unwrap()
+            .hash(true)
+    );
+    assert_eq!(pos.side_to_move(), Color::WHITE);
+    assert!(pos
+        .legal()
+        .into_iter()
+        .all(|mv| pos.color_on(mv.from()) == Some(Color::WHITE)));
+}
+
+#[test]
+fn open_files() {
+    use crate::types::BitBoard;
+
+    // The d-file is cleared, white has no e-pawn and black has no c-pawn
+    let pos = Position::from_str("r2qk2r/pp3ppp/4p3/8/2P5/8/PP3PPP/R2QK2R w KQkq - 0 1").unwrap();
+    assert_eq!(pos.open_files(), BitBoard::FILE_D);
+    assert_eq!(pos.half_open_files(Color::WHITE), BitBoard::FILE_E);
+    assert_eq!(pos.half_open_files(Color::BLACK), BitBoard::FILE_C);
+    assert_eq!(Position::default().open_files(), 0);
+}
+
+#[test]
+fn king_zone_attackers() {
+    // The queen, the knight on g5 and the bishop on d3 aim at the castled king, the rook on a1
+    // and the knight on c3 don't
+    let pos =
+        Position::from_str("r1bq1rk1/ppp2ppp/2n5/6NQ/8/2NB4/PPP2PPP/R3K2R w KQ - 0 1").unwrap();
+    assert_eq!(pos.king_zone_attackers(Color::BLACK), 3);
+    assert_eq!(pos.king_zone_attackers(Color::WHITE), 0);
+    assert_eq!(Position::default().king_zone_attackers(Color::WHITE), 0);
+}
+
+#[test]
+fn piece_at() {
+    let pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    for sq in Square::iter() {
+        let untyped = pos.piece_on(sq).zip(pos.color_on(sq));
+        assert_eq!(
+            pos.piece_at(sq).map(|(p, c)| (p.index(), c.index())),
+            untyped
+        );
+    }
+}
+
+#[test]
+fn square_distance() {
+    let pairs = [
+        (Square::E4, Square::E4, 0, 0),
+        (Square::E4, Square::E5, 1, 1),
+        (Square::E4, Square::F5, 1, 2),
+        (Square::H1, Square::A2, 7, 8),
+        (Square::A1, Square::H8, 7, 14),
+        (Square::B7, Square::G2, 5, 10),
+    ];
+    for (a, b, distance, manhattan) in pairs {
+        assert_eq!(Square::distance(a, b), distance);
+        assert_eq!(Square::distance(b, a), distance);
+        assert_eq!(Square::manhattan(a, b), manhattan);
+        assert_eq!(Square::manhattan(b, a), manhattan);
+    }
+}
+
+#[test]
+fn between_and_line() {
+    use crate::{between, line};
+
+    // Collinear pairs, on a file, a rank and both diagonals
+    assert_eq!(
+        between(Square::E1, Square::E4),
+        1 << Square::E2 | 1 << Square::E3
+    );
+    assert_eq!(between(Square::H5, Square::F5), 1 << Square::G5);
+    assert_eq!(
+        between(Square::H1, Square::E4),
+        1 << Square::G2 | 1 << Square::F3
+    );
+    assert_eq!(between(Square::E4, Square::E5), 0);
+    assert_eq!(
+        line(Square::E1, Square::E4),
+        1 << Square::E1 | 1 << Square::E2 | 1 << Square::E3 | 1 << Square::E4
+    );
+    assert_eq!(
+        line(Square::G2, Square::F3),
+        1 << Square::G2 | 1 << Square::F3
+    );
+    assert_eq!(line(Square::B2, Square::A1), line(Square::A1, Square::B2));
+
+    // Non-collinear pairs
+    for (a, b) in [(Square::A1, Square::B3), (Square::E4, Square::H8)] {
+        assert_eq!(between(a, b), 0);
+        assert_eq!(line(a, b), 0);
+    }
+}
+
+#[test]
+fn leaper_attacks() {
+    use crate::{king_attacks, knight_attacks, pawn_attacks};
+
+    assert_eq!(knight_attacks(Square::A1).count_ones(), 2);
+    assert_eq!(knight_attacks(Square::E4).count_ones(), 8);
+    assert_eq!(
+        knight_attacks(Square::H8),
+        1 << Square::G6 | 1 << Square::F7
+    );
+    assert_eq!(king_attacks(Square::A1).count_ones(), 3);
+    assert_eq!(king_attacks(Square::E4).count_ones(), 8);
+    assert_eq!(king_attacks(Square::H5).count_ones(), 5);
+    assert_eq!(
+        pawn_attacks(Square::E4, Color::WHITE),
+        1 << Square::D5 | 1 << Square::F5
+    );
+    assert_eq!(
+        pawn_attacks(Square::E4, Color::BLACK),
+        1 << Square::D3 | 1 << Square::F3
+    );
+    assert_eq!(pawn_attacks(Square::A2, Color::WHITE), 1 << Square::B3);
+    assert_eq!(pawn_attacks(Square::H7, Color::BLACK), 1 << Square::G6);
+}
+
+#[test]
+fn slider_attacks() {
+    use crate::{bishop_attacks, queen_attacks, rook_attacks};
+
+    // Rook on e1 blocked on e4 and c1, the blockers are attacked too
+    let occ = 1 << Square::E4 | 1 << Square::E7 | 1 << Square::C1;
+    let mut expected = 0;
+    for sq in [
+        Square::E2,
+        Square::E3,
+        Square::E4,
+        Square::D1,
+        Square::C1,
+        Square::F1,
+        Square::G1,
+        Square::H1,
+    ] {
+        expected |= 1 << sq;
+    }
+    assert_eq!(rook_attacks(Square::E1, occ), expected);
+
+    // Bishop on c1 blocked on e3, free towards a3
+    let occ = 1 << Square::E3 | 1 << Square::F4;
+    assert_eq!(
+        bishop_attacks(Square::C1, occ),
+        1 << Square::D2 | 1 << Square::E3 | 1 << Square::B2 | 1 << Square::A3
+    );
+    assert_eq!(bishop_attacks(Square::D4, 0).count_ones(), 13);
+    assert_eq!(rook_attacks(Square::D4, 0).count_ones(), 14);
+    assert_eq!(
+        queen_attacks(Square::D4, occ),
+        rook_attacks(Square::D4, occ) | bishop_attacks(Square::D4, occ)
+    );
+}
+
+#[test]
+fn pseudo_legal_contains_legal() {
+    let fens = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    ];
+    for fen in fens {
+        let mut pos = Position::from_str(fen).unwrap();
+        // Check the positions one move deep too, to get checks, pins and en passants
+        for mv in pos.legal() {
+            pos.make_move(mv);
+            let pseudo = pos.pseudo_legal();
+            for reply in pos.legal() {
+                assert!(pseudo.contains(reply), "{fen} {mv} {reply}");
+            }
+            pos.undo_move(mv);
+        }
+        let pseudo = pos.pseudo_legal();
+        assert!(pos.legal().into_iter().all(|mv| pseudo.contains(mv)));
+    }
+    assert_eq!(Position::default().pseudo_legal().count(), 20);
+}
+
+#[test]
+fn legal_verified() {
+    let fens = [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        // En passant that would expose the king on the rank
+        "8/8/8/K2pP2r/8/8/8/7k w - d6 0 1",
+        // En passant capturing the checking pawn, and a pinned pawn that can't take
+        "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        "8/8/8/8/k2Pp2Q/8/8/3K4 b - d3 0 1",
+        "4k3/8/8/1b6/3pP3/8/8/7K b - e3 0 1",
+    ];
+    for fen in fens {
+        let mut pos = Position::from_str(fen).unwrap();
+        for mv in pos.legal_verified() {
+            pos.make_move(mv);
+            let mut legal = pos.legal().to_vec();
+            let mut verified = pos.legal_verified().to_vec();
+            legal.sort();
+            verified.sort();
+            assert_eq!(legal, verified, "{fen} {mv}");
+            pos.undo_move(mv);
+        }
+        let mut legal = pos.legal().to_vec();
+        let mut verified = pos.legal_verified().to_vec();
+        legal.sort();
+        verified.sort();
+        assert_eq!(legal, verified, "{fen}");
+    }
+}
+
+#[test]
+fn legal_queen_promotions_only() {
+    use crate::GenOptions;
+
+    let pos = Position::from_str("r1n1k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let options = GenOptions {
+        queen_promotions_only: true,
+    };
+    let all = pos.legal();
+    let restricted = pos.legal_with(options);
+    // b7-b8, b7xa8 and b7xc8
+    assert_eq!(all.count_promotions(), 12);
+    assert_eq!(restricted.count_promotions(), 3);
+    assert_eq!(restricted.count(), all.count() - 9);
+    assert!(restricted
+        .into_iter()
+        .filter(|mv| mv.is_promotion())
+        .all(|mv| mv.promotion_piece() == Some(Piece::QUEEN)));
+    assert_eq!(
+        pos.legal_with(GenOptions::default()).as_slice(),
+        all.as_slice()
+    );
+}
+
+#[test]
+fn move_raw() {
+    for flag in Move::QUIET..=Move::PC_Q {
+        let mv = Move::new(Square::G7, Square::H8, flag);
+        assert_eq!(
+            mv.to_raw(),
+            (Square::G7 | Square::H8 << 6 | flag << 12) as u16
+        );
+        assert_eq!(Move::from_raw(mv.to_raw()), mv);
+    }
+    assert_eq!(Move::from_raw(Move::NULL.to_raw()), Move::NULL);
+}
+
+#[test]
+fn movelist_max_moves() {
+    let pos = Position::from_str("R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1").unwrap();
+    assert_eq!(pos.legal().count(), 218);
+    assert_eq!(pos.legal_verified().count(), 218);
+}
+
+#[test]
+fn position_eq_transposition() {
+    let mut a = Position::default();
+    let mut b = Position::default();
+    a.make_move(Move::new(Square::G1, Square::F3, Move::QUIET));
+    a.make_move(Move::new(Square::G8, Square::F6, Move::QUIET));
+    a.make_move(Move::new(Square::B1, Square::C3, Move::QUIET));
+    b.make_move(Move::new(Square::B1, Square::C3, Move::QUIET));
+    b.make_move(Move::new(Square::G8, Square::F6, Move::QUIET));
+    b.make_move(Move::new(Square::G1, Square::F3, Move::QUIET));
+    assert_eq!(a, b);
+    assert_ne!(a, Position::default());
+
+    // Same pieces, but different castling rights or en passant square
+    let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2";
+    let mut c = Position::from_str(fen).unwrap();
+    assert_eq!(c, Position::from_str(fen).unwrap());
+    c.set_en_passant(None);
+    assert_ne!(c, Position::from_str(fen).unwrap());
+    c.set_en_passant(Some(Square::E6));
+    c.set_castling_rights(0);
+    assert_ne!(c, Position::from_str(fen).unwrap());
+}
+
+#[test]
+fn position_hash_set() {
+    use std::collections::HashSet;
+
+    let mut seen = HashSet::new();
+    let mut pos = Position::default();
+    pos.make_move(Move::new(Square::E2, Square::E3, Move::QUIET));
+    pos.make_move(Move::new(Square::E7, Square::E6, Move::QUIET));
+    pos.make_move(Move::new(Square::D2, Square::D3, Move::QUIET));
+    assert!(seen.insert(pos));
+
+    let mut other = Position::default();
+    other.make_move(Move::new(Square::D2, Square::D3, Move::QUIET));
+    other.make_move(Move::new(Square::E7, Square::E6, Move::QUIET));
+    assert!(!seen.contains(&other));
+    other.make_move(Move::new(Square::E2, Square::E3, Move::QUIET));
+    assert!(seen.contains(&other));
+    assert!(!seen.insert(other));
+    assert_eq!(seen.len(), 1);
+}
+
+#[test]
+fn fork() {
+    let mut pos = Position::default();
+    let moves = [
+        Move::new(Square::G1, Square::F3, Move::QUIET),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::F3, Square::G1, Move::QUIET),
+        Move::new(Square::F6, Square::G8, Move::QUIET),
+    ];
+    for mv in moves {
+        pos.make_move(mv);
+    }
+    let fork = pos.fork();
+    assert_eq!(fork.ply(), 0);
+    assert_eq!(pos.ply(), 4);
+    assert_eq!(fork.fen(), pos.fen());
+    assert_eq!(fork.hash(true), pos.hash(true));
+    assert_eq!(pos.repetition_count(), 2);
+    assert_eq!(fork.repetition_count(), 1);
+}
+
+#[test]
+fn is_in_check() {
+    // White to move, but it's black that is attacked by the bishop
+    let pos = Position::from_str("4k3/8/8/1B6/8/8/8/4K3 w - - 0 1").unwrap();
+    assert!(!pos.in_check());
+    assert!(!pos.is_in_check(Color::WHITE));
+    assert!(pos.is_in_check(Color::BLACK));
+    assert!(pos.is_square_attacked(Square::D7, Color::WHITE));
+    assert!(pos.is_square_attacked(Square::D7, Color::BLACK));
+    assert!(!pos.is_square_attacked(Square::D6, Color::WHITE));
+
+    let pos = Position::from_str("4k3/8/8/1B6/8/8/8/4K3 b - - 0 1").unwrap();
+    assert!(pos.in_check());
+    assert_eq!(pos.in_check(), pos.is_in_check(Color::BLACK));
+}
+
+#[test]
+fn discovers_check() {
+    // The rook on e1 is behind the bishop on e4, aiming at the king on e8
+    let pos = Position::from_str("4k3/8/8/8/4B3/8/8/K3R3 w - - 0 1").unwrap();
+    for mv in pos.legal() {
+        assert_eq!(pos.discovers_check(mv), mv.from() == Square::E4, "{mv}");
+    }
+
+    // The pawn keeps blocking when it's pushed, and a black piece in the way stops the discovery
+    let pos = Position::from_str("4k3/8/8/8/4P3/8/8/K3R3 w - - 0 1").unwrap();
+    assert!(!pos.discovers_check(Move::new(Square::E4, Square::E5, Move::QUIET)));
+    let pos = Position::from_str("4k3/4n3/8/8/4B3/8/8/K3R3 w - - 0 1").unwrap();
+    assert!(!pos.discovers_check(Move::new(Square::E4, Square::D5, Move::QUIET)));
+
+    // Diagonal battery with a knight in front of the queen
+    let pos = Position::from_str("7k/8/8/8/8/2N5/1Q6/K7 w - - 0 1").unwrap();
+    assert!(pos.discovers_check(Move::new(Square::C3, Square::E4, Move::QUIET)));
+    assert!(!pos.discovers_check(Move::new(Square::B2, Square::B3, Move::QUIET)));
+}
+
+#[test]
+fn pin_masks() {
+    // The rook on e8 pins the knight on e4 and the bishop on a5 pins the pawn on c3
+    let pos = Position::from_str("4r2k/8/8/b7/4N3/2P5/8/4K3 w - - 0 1").unwrap();
+    let ray = |squares: &[usize]| squares.iter().fold(0u64, |bb, &sq| bb | 1 << sq);
+    assert_eq!(
+        pos.pinned_hv(),
+        ray(&[
+            Square::E2,
+            Square::E3,
+            Square::E4,
+            Square::E5,
+            Square::E6,
+            Square::E7,
+            Square::E8
+        ])
+    );
+    assert_eq!(
+        pos.pinned_d12(),
+        ray(&[Square::D2, Square::C3, Square::B4, Square::A5])
+    );
+    assert_eq!(pos.pinned_hv() & pos.colors(Color::WHITE), 1 << Square::E4);
+    assert_eq!(pos.pinned_d12() & pos.colors(Color::WHITE), 1 << Square::C3);
+    assert_eq!(pos.pinned(), pos.pinned_hv() | pos.pinned_d12());
+
+    // The squares behind the king on the rook's ray are dangerous too
+    let pos = Position::from_str("4r2k/8/8/8/8/8/4K3/8 w - - 0 1").unwrap();
+    assert_ne!(pos.danger() & 1 << Square::E1, 0);
+    assert_eq!(pos.danger(), pos.attacks());
+}
+
+#[test]
+fn blockers_and_pinners() {
+    let fens = [
+        "4r2k/8/8/b7/4N3/2P5/8/4K3 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "rnbqkbnr/ppp2ppp/8/1B1pp3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 3",
+        "3k4/3r4/8/3B4/8/3K4/8/8 w - - 0 1",
+    ];
+    for fen in fens {
+        let mut pos = Position::from_str(fen).unwrap();
+        for color in [Color::WHITE, Color::BLACK] {
+            pos.set_turn(color);
+            let own = pos.colors(color);
+            let enemy = pos.colors(1 - color);
+            assert_eq!(
+                pos.blockers_for_king(color) & own,
+                pos.pinned() & own,
+                "{fen}"
+            );
+            assert_eq!(pos.pinners(color), pos.pinned() & enemy, "{fen}");
+        }
+    }
+
+    // The bishop on d5 is pinned by the rook on d7, and nothing aims at the black king
+    let pos = Position::from_str("3k4/3r4/8/3B4/8/3K4/8/8 w - - 0 1").unwrap();
+    assert_eq!(pos.blockers_for_king(Color::WHITE), 1 << Square::D5);
+    assert_eq!(pos.pinners(Color::WHITE), 1 << Square::D7);
+    assert_eq!(pos.blockers_for_king(Color::BLACK), 0);
+    assert_eq!(pos.pinners(Color::BLACK), 0);
+}
+
+#[test]
+fn bitboard_files_and_ranks() {
+    use crate::types::BitBoard;
+
+    assert_eq!(BitBoard::file(0), BitBoard::FILE_A);
+    assert_eq!(BitBoard::file(7), BitBoard::FILE_H);
+    assert_eq!(BitBoard::rank(0), BitBoard::RANK_1);
+    assert_eq!(BitBoard::rank(7), BitBoard::RANK_8);
+    assert_eq!(BitBoard::adjacent_files(0), BitBoard::FILE_B);
+    assert_eq!(BitBoard::adjacent_files(7), BitBoard::FILE_G);
+    assert_eq!(
+        BitBoard::adjacent_files(4),
+        BitBoard::FILE_D | BitBoard::FILE_F
+    );
+    for i in 0..8 {
+        assert_eq!(BitBoard::file(i).count_ones(), 8);
+        assert_eq!(BitBoard::rank(i).count_ones(), 8);
+        assert_eq!(BitBoard::file(i) & BitBoard::rank(i), 1 << (i * 8 + i));
+    }
+}
+
+#[test]
+fn front_spans() {
+    use crate::types::BitBoard;
+    use crate::{front_span, front_span_wide};
+
+    let e_ahead = BitBoard::FILE_E & !((1 << Square::E5) - 1);
+    assert_eq!(front_span(Square::E4, Color::WHITE), e_ahead);
+    assert_eq!(
+        front_span_wide(Square::E4, Color::WHITE),
+        e_ahead | e_ahead >> 1 | e_ahead << 1
+    );
+    assert_eq!(
+        front_span(Square::E4, Color::BLACK),
+        1 << Square::E3 | 1 << Square::E2 | 1 << Square::E1
+    );
+    assert_eq!(front_span(Square::E8, Color::WHITE), 0);
+    assert_eq!(front_span(Square::E1, Color::BLACK), 0);
+
+    // No wraparound between the a and h files
+    let a_wide = front_span_wide(Square::A4, Color::WHITE);
+    assert_eq!(a_wide & BitBoard::FILE_H, 0);
+    assert_eq!(
+        a_wide,
+        (BitBoard::FILE_A | BitBoard::FILE_B) & !((1 << Square::A5) - 1)
+    );
+    assert_eq!(
+        front_span_wide(Square::H4, Color::BLACK) & BitBoard::FILE_A,
+        0
+    );
+}
+
+#[test]
+fn square_colors() {
+    use crate::types::BitBoard;
+
+    assert!(!Square::is_light(Square::A1));
+    assert!(Square::is_light(Square::H1));
+    assert!(Square::is_light(Square::A8));
+    assert!(!Square::is_light(Square::H8));
+    assert_eq!(BitBoard::LIGHT_SQUARES & BitBoard::DARK_SQUARES, 0);
+    assert_eq!(BitBoard::LIGHT_SQUARES | BitBoard::DARK_SQUARES, u64::MAX);
+    for sq in Square::iter() {
+        assert_eq!(Square::is_light(sq), BitBoard::LIGHT_SQUARES & 1 << sq != 0);
+    }
+}
+
+#[test]
+fn movelist_display() {
+    use crate::types::MoveList;
+
+    let mut list = MoveList::new();
+    assert_eq!(list.to_string(), "");
+    list.add(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    list.add(Square::G7, Square::H8, Move::PC_Q);
+    assert_eq!(list.to_string(), "e2e4 g7h8q");
+    assert_eq!(format!("{list:#}"), "e2e4\ng7h8q");
+}
+
+#[test]
+fn movelist_retain() {
+    let pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let mut list = pos.legal();
+    let captures = list.count_captures();
+    let order = list
+        .into_iter()
+        .filter(|mv| mv.is_capture())
+        .collect::<Vec<_>>();
+    list.retain(|mv| mv.is_capture());
+    assert_eq!(list.count(), captures);
+    assert_eq!(list.count_captures(), captures);
+    assert_eq!(list.as_slice(), order);
+
+    let mut list = pos.legal();
+    list.filter_from(Square::E5);
+    assert!(list.into_iter().all(|mv| mv.from() == Square::E5));
+    assert_eq!(list.count(), 7);
+}
+
+#[test]
+fn movelist_sort_by_key() {
+    let mut list = Position::default().legal();
+    let original = list.to_vec();
+    list.sort_by_key(|mv| mv.to());
+    assert!(list.as_slice().windows(2).all(|w| w[0].to() <= w[1].to()));
+    // Ties keep the generation order, like Nb1-c3 and c2-c3
+    let c3 = list
+        .into_iter()
+        .filter(|mv| mv.to() == Square::C3)
+        .collect::<Vec<_>>();
+    let expected = original
+        .into_iter()
+        .filter(|mv| mv.to() == Square::C3)
+        .collect::<Vec<_>>();
+    assert_eq!(c3, expected);
+    assert_eq!(list.count(), 20);
+}
+
+#[test]
+fn long_game() {
+    let mut pos = Position::default();
+    let moves = [
+        Move::new(Square::G1, Square::F3, Move::QUIET),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::F3, Square::G1, Move::QUIET),
+        Move::new(Square::F6, Square::G8, Move::QUIET),
+    ];
+    // The history grows past the 215 plies the old fixed array could hold
+    for i in 0..1000 {
+        pos.make_move(moves[i % 4]);
+    }
+    assert_eq!(pos.ply(), 1000);
+    assert_eq!(
+        pos.fen(),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 1000 501"
+    );
+    for i in (0..1000).rev() {
+        pos.undo_move(moves[i % 4]);
+    }
+    assert_eq!(pos, Position::default());
+    assert_eq!(pos.ply(), 0);
+}
+
+#[test]
+fn undo_without_move() {
+    let start = Position::default();
+    let mut pos = start.clone();
+    let moves = [
+        Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH),
+        Move::new(Square::D7, Square::D5, Move::DOUBLE_PUSH),
+        Move::new(Square::E4, Square::D5, Move::CAPTURE),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::F1, Square::B5, Move::QUIET),
+        Move::new(Square::C7, Square::C6, Move::QUIET),
+        Move::new(Square::G1, Square::F3, Move::QUIET),
+        Move::new(Square::C6, Square::B5, Move::CAPTURE),
+        Move::new(Square::E1, Square::G1, Move::CASTLE_00),
+    ];
+    for mv in moves {
+        pos.make_move(mv);
+    }
+    pos.make_null_move();
+    assert_eq!(pos.undo(), Some(Move::NULL));
+    for mv in moves.into_iter().rev() {
+        assert_eq!(pos.undo(), Some(mv));
+    }
+    assert_eq!(pos.undo(), None);
+    assert_eq!(pos.fen(), start.fen());
+    assert_eq!(pos.hash(true), start.hash(true));
+}
+
+#[test]
+fn undo_redo_navigation() {
+    let mut pos = Position::default();
+    let e4 = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH);
+    let e5 = Move::new(Square::E7, Square::E5, Move::DOUBLE_PUSH);
+    let nf3 = Move::new(Square::G1, Square::F3, Move::QUIET);
+    for mv in [e4, e5, nf3] {
+        pos.make_move(mv);
+    }
+    let end = pos.fen();
+
+    // Back, back, forward, forward
+    assert_eq!(pos.undo(), Some(nf3));
+    assert_eq!(pos.undo(), Some(e5));
+    assert_eq!(pos.redo(), Some(e5));
+    assert_eq!(pos.redo(), Some(nf3));
+    assert_eq!(pos.redo(), None);
+    assert_eq!(pos.fen(), end);
+
+    // Making the undone move again keeps the rest of the line
+    pos.undo();
+    pos.undo();
+    pos.make_move(e5);
+    assert_eq!(pos.redo(), Some(nf3));
+
+    // Diverging from the undone line forgets it
+    pos.undo();
+    pos.undo();
+    pos.make_move(Move::new(Square::C7, Square::C5, Move::DOUBLE_PUSH));
+    assert_eq!(pos.redo(), None);
+    assert_eq!(pos.ply(), 2);
+}
+
+#[test]
+fn game_scholars_mate() {
+    use crate::game::{Game, GameResult};
+    use crate::types::MoveError;
+
+    let mut game = Game::new();
+    assert_eq!(
+        game.push(Move::new(Square::E4, Square::E5, Move::QUIET)),
+        Err(MoveError::EmptySquare)
+    );
+    assert_eq!(
+        game.push(Move::new(Square::E2, Square::E4, Move::CAPTURE)),
+        Err(MoveError::Illegal)
+    );
+    assert_eq!(game.moves().count(), 0);
+    let moves = [
+        Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH),
+        Move::new(Square::E7, Square::E5, Move::DOUBLE_PUSH),
+        Move::new(Square::F1, Square::C4, Move::QUIET),
+        Move::new(Square::B8, Square::C6, Move::QUIET),
+        Move::new(Square::D1, Square::H5, Move::QUIET),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::H5, Square::F7, Move::CAPTURE),
+    ];
+    for mv in moves {
+        assert_eq!(game.result(), GameResult::Ongoing);
+        game.push(mv).unwrap();
+    }
+    assert_eq!(game.result(), GameResult::WhiteWins);
+    assert_eq!(
+        game.pgn(),
+        "[Result \"1-0\"]\n\n1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0"
+    );
+    assert!(game
+        .push(Move::new(Square::E8, Square::F7, Move::CAPTURE))
+        .is_err());
+    // Bad moves are rejected without touching the game
+    assert_eq!(
+        game.push(Move::new(Square::E3, Square::E2, Move::QUIET)),
+        Err(MoveError::EmptySquare)
+    );
+    assert_eq!(
+        game.push(Move::new(Square::F7, Square::F8, Move::QUIET)),
+        Err(MoveError::NotYourPiece)
+    );
+    assert_eq!(game.moves().count(), 7);
+
+    assert_eq!(game.pop(), Some(moves[6]));
+    assert_eq!(game.result(), GameResult::Ongoing);
+    assert_eq!(game.moves().count(), 6);
+}
+
+#[test]
+fn set_fen_reuses_position() {
+    let mut pos = Position::default();
+    pos.make_move(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH));
+
+    // Black is in check from the bishop, so only a few moves are legal
+    let fen = "rnbqkbnr/ppp2ppp/8/1B1pp3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 1 3";
+    pos.set_fen(fen).unwrap();
+    let parsed = Position::from_str(fen).unwrap();
+    assert_eq!(pos.fen(), fen);
+    assert_eq!(pos.ply(), 0);
+    assert_eq!(pos.hash(true), parsed.hash(true));
+    assert!(pos.in_check());
+    assert_eq!(pos.legal().count(), parsed.legal().count());
+    assert_eq!(pos.legal().count(), 6);
+    assert_eq!(pos.undo(), None);
+
+    assert!(pos.set_fen("8/8/8 w - - 0 1").is_err());
+    pos.set_fen(&Position::default().fen()).unwrap();
+    assert_eq!(pos, Position::default());
+    assert_eq!(pos.hash(true), Position::default().hash(true));
+}
+
+#[test]
+fn reset_to_startpos() {
+    let mut pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    pos.make_move(Move::new(Square::E1, Square::G1, Move::CASTLE_00));
+    pos.reset();
+    assert_eq!(pos.fen(), Position::START_FEN);
+    assert_eq!(pos.hash(true), Position::startpos().hash(true));
+    assert_eq!(pos, Position::default());
+    assert_eq!(pos.ply(), 0);
+}
+
+#[test]
+fn hash_after_undo() {
+    // White can castle both ways, capture en passant on e6 and promote on a8 and b8
+    let fen = "1n2k2r/P7/8/2pPp3/8/8/8/R3K2R w KQk e6 0 1";
+    let mut pos = Position::from_str(fen).unwrap();
+    let moves = [
+        Move::new(Square::E1, Square::G1, Move::CASTLE_00),
+        Move::new(Square::E1, Square::C1, Move::CASTLE_000),
+        Move::new(Square::D5, Square::E6, Move::EN_PASSANT),
+        Move::new(Square::A7, Square::A8, Move::PR_Q),
+        Move::new(Square::A7, Square::B8, Move::PC_N),
+        Move::new(Square::H1, Square::H8, Move::CAPTURE),
+    ];
+    let legal = pos.legal();
+    for mv in moves {
+        assert!(legal.contains(mv), "{mv}");
+        let before = pos.recompute_hash();
+        pos.make_move(mv);
+        assert_eq!(pos.hash(true), pos.recompute_hash(), "{mv}");
+        // The replies include black castling and capturing the promoted piece
+        for reply in pos.legal() {
+            pos.make_move(reply);
+            assert_eq!(pos.hash(true), pos.recompute_hash(), "{mv} {reply}");
+            pos.undo_move(reply);
+        }
+        pos.undo_move(mv);
+        assert_eq!(pos.hash(true), before, "{mv}");
+    }
+}
+
+#[test]
+fn material_balance() {
+    let mut pos = Position::default();
+    assert_eq!(pos.material_balance(), 0);
+    // 1. e4 d5 2. exd5 c6 3. dxc6 Qb6 4. cxb7 Qb5 5. bxa8=Q
+    let moves = [
+        Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH),
+        Move::new(Square::D7, Square::D5, Move::DOUBLE_PUSH),
+        Move::new(Square::E4, Square::D5, Move::CAPTURE),
+        Move::new(Square::C7, Square::C6, Move::QUIET),
+        Move::new(Square::D5, Square::C6, Move::CAPTURE),
+        Move::new(Square::D8, Square::B6, Move::QUIET),
+        Move::new(Square::C6, Square::B7, Move::CAPTURE),
+        Move::new(Square::B6, Square::B5, Move::QUIET),
+        Move::new(Square::B7, Square::A8, Move::PC_Q),
+    ];
+    let mut balances = Vec::new();
+    for mv in moves {
+        assert!(pos.legal().contains(mv), "{mv}");
+        pos.make_move(mv);
+        assert_eq!(pos.material_balance(), pos.material(), "{mv}");
+        balances.push(pos.material_balance());
+    }
+    // Three pawns and a rook up, with the pawn that captured the rook now a queen
+    assert_eq!(pos.material_balance(), 3 * 100 + 500 + 900 - 100);
+    assert_eq!(balances[2], 100);
+
+    pos.make_move(Move::new(Square::B5, Square::F1, Move::CAPTURE));
+    assert_eq!(pos.material_balance(), pos.material());
+    while pos.undo().is_some() {
+        assert_eq!(pos.material_balance(), pos.material());
+    }
+    assert_eq!(pos.material_balance(), 0);
+}
+
+#[test]
+fn good_capture_agrees_with_see() {
+    let fens = [
+        "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "4k3/8/8/3r4/4P3/8/8/3RK3 w - - 0 1",
+        "4k3/8/8/2pP4/8/8/8/4K3 w - c6 0 2",
+    ];
+    for fen in fens {
+        let pos = Position::from_str(fen).unwrap();
+        for mv in pos.legal() {
+            if !mv.is_capture() && !mv.is_promotion() {
+                continue;
+            }
+            let see = pos.see(mv);
+            for threshold in (-1000..=1000).step_by(10) {
+                assert_eq!(
+                    pos.is_good_capture(mv, threshold),
+                    see >= threshold,
+                    "{fen} {mv} {see} {threshold}"
+                );
+            }
+        }
+    }
+
+    // Nxe5 wins a pawn for a knight
+    let pos = Position::from_str(fens[0]).unwrap();
+    assert_eq!(
+        pos.see(Move::new(Square::D3, Square::E5, Move::CAPTURE)),
+        -220
+    );
+    // exd5 wins a rook, and the rook recapturing loses it
+    let pos = Position::from_str(fens[4]).unwrap();
+    assert_eq!(
+        pos.see(Move::new(Square::E4, Square::D5, Move::CAPTURE)),
+        500
+    );
+    assert_eq!(
+        pos.see(Move::new(Square::D1, Square::D5, Move::CAPTURE)),
+        500
+    );
+    // En passant
+    let pos = Position::from_str(fens[5]).unwrap();
+    assert_eq!(
+        pos.see(Move::new(Square::D5, Square::C6, Move::EN_PASSANT)),
+        100
+    );
+}
+
+#[test]
+fn dead_positions() {
+    let dead = [
+        "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+        "4k3/8/8/8/8/8/8/1N2K3 w - - 0 1",
+        "4kb2/8/8/8/8/8/8/4K3 b - - 0 1",
+        // Bishops on dark squares only
+        "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        "3bkb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+    ];
+    for fen in dead {
+        assert!(Position::from_str(fen).unwrap().is_dead(), "{fen}");
+    }
+    let alive = [
+        // Two knights can't force a mate, but a mate can still happen
+        "4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1",
+        // Bishops on squares of different colors
+        "4k1b1/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        "4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1",
+        "4kn2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+    ];
+    for fen in alive {
+        assert!(!Position::from_str(fen).unwrap().is_dead(), "{fen}");
+    }
+}
+
+#[test]
+fn lan() {
+    let pos =
+        Position::from_str("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 2 3")
+            .unwrap();
+    assert_eq!(
+        pos.to_lan(Move::new(Square::F3, Square::F7, Move::CAPTURE)),
+        "Qf3xf7#"
+    );
+    assert_eq!(
+        pos.to_lan(Move::new(Square::C4, Square::F7, Move::CAPTURE)),
+        "Bc4xf7+"
+    );
+
+    let pos = Position::from_str("r3k3/1P6/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+    assert_eq!(
+        pos.to_lan(Move::new(Square::E1, Square::G1, Move::CASTLE_00)),
+        "O-O"
+    );
+    assert_eq!(
+        pos.to_lan(Move::new(Square::E1, Square::C1, Move::CASTLE_000)),
+        "O-O-O"
+    );
+    assert_eq!(
+        pos.to_lan(Move::new(Square::B7, Square::A8, Move::PC_Q)),
+        "b7xa8=Q+"
+    );
+    assert_eq!(
+        pos.to_lan(Move::new(Square::B7, Square::B8, Move::PR_N)),
+        "b7-b8=N"
+    );
+}
+
+#[test]
+fn shredder_fen() {
+    // The standard start position is also the Chess960 start position 518
+    let mut pos = Position::default();
+    assert_eq!(
+        pos.fen_chess960(),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"
+    );
+    assert_eq!(pos.fen(), Position::START_FEN);
+
+    let pos2 = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1").unwrap();
+    assert_eq!(pos2.fen_chess960(), "r3k2r/8/8/8/8/8/8/R3K2R w Ha - 0 1");
+
+    pos.set_castling_rights(0);
+    assert_eq!(
+        pos.fen_chess960(),
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1"
+    );
+}
+
+#[test]
+fn repetition_over_full_history() {
+    let mut pos = Position::default();
+    let shuffle = [
+        Move::new(Square::G1, Square::F3, Move::QUIET),
+        Move::new(Square::G8, Square::F6, Move::QUIET),
+        Move::new(Square::F3, Square::G1, Move::QUIET),
+        Move::new(Square::F6, Square::G8, Move::QUIET),
+    ];
+    for mv in shuffle {
+        pos.make_move(mv);
+    }
+    assert!(pos.has_cycle());
+    assert_eq!(pos.repetition_count_full(), 2);
+
+    // The pawn move is irreversible, so the positions before it can't come back
+    pos.make_move(Move::new(Square::E2, Square::E3, Move::QUIET));
+    pos.make_move(Move::new(Square::E7, Square::E6, Move::QUIET));
+    assert!(!pos.has_cycle());
+    for _ in 0..2 {
+        for mv in shuffle {
+            pos.make_move(mv);
+        }
+    }
+    assert_eq!(pos.repetition_count_full(), 3);
+    assert_eq!(pos.repetition_count(), 3);
+
+    // Only the positions with the same side to move are compared
+    pos.make_null_move();
+    assert_eq!(pos.repetition_count_full(), 1);
+    pos.undo_null_move();
+
+    pos.make_move(shuffle[0]);
+    assert_eq!(pos.repetition_count_full(), 3);
+    assert_eq!(pos.fork().repetition_count_full(), 1);
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn random_playout_is_deterministic() {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let playout = |seed: u64| {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut pos = Position::default();
+        let mut moves = Vec::new();
+        while let Some(mv) = pos.random_legal(&mut rng) {
+            assert!(pos.legal().contains(mv));
+            pos.make_move(mv);
+            moves.push(mv);
+            if moves.len() == 200 {
+                break;
+            }
+        }
+        moves
+    };
+    assert_eq!(playout(7), playout(7));
+    assert_ne!(playout(7), playout(8));
+
+    // Checkmated, so there's nothing to pick
+    let pos =
+        Position::from_str("r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4")
+            .unwrap();
+    assert_eq!(pos.random_legal(&mut SmallRng::seed_from_u64(7)), None);
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn random_positions() {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let mut rng = SmallRng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let pos = random_position(&mut rng);
+        let fen = pos.fen();
+        assert_eq!(pos.is_valid(), Ok(()), "{fen}");
+        // The FEN round-trips, so the castling rights and en passant square can be parsed back
+        assert_eq!(Position::from_str(&fen).unwrap().fen(), fen);
+    }
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn fuzz_legal_against_verified() {
+    fuzz_movegen(500, 0);
+}
+
+#[test]
+fn invalid_positions() {
+    let invalid = [
+        ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", "White has 2 kings"),
+        (
+            "3Pk3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "Pawn on the first or last rank on d8",
+        ),
+        (
+            "4k3/8/8/8/8/8/8/4R2K w - - 0 1",
+            "Black is in check but it isn't its turn",
+        ),
+        (
+            "4k3/8/8/8/8/8/8/R3K3 w K - 0 1",
+            "White can castle without the king and rook on e1 and h1",
+        ),
+        (
+            "r2k3r/8/8/8/8/8/8/4K3 w q - 0 1",
+            "Black can castle without the king and rook on e8 and a8",
+        ),
+        // There is no black pawn on e5
+        (
+            "4k3/8/8/8/8/8/8/4K3 w - e6 0 1",
+            "No pawn could have just pushed past e6",
+        ),
+        // The pawn can't have come from e7
+        (
+            "4k3/4b3/8/4p3/8/8/8/4K3 w - e6 0 1",
+            "No pawn could have just pushed past e6",
+        ),
+        // It's white's turn, so white can't have pushed
+        (
+            "4k3/8/8/8/4P3/8/8/4K3 w - e3 0 1",
+            "No pawn could have just pushed past e3",
+        ),
+    ];
+    for (fen, error) in invalid {
+        let pos = Position::from_str(fen).unwrap();
+        assert_eq!(pos.is_valid(), Err(String::from(error)), "{fen}");
+    }
+    let valid = [
+        "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+        "r3k2r/8/8/8/8/8/8/4K3 w kq - 0 1",
+        "4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1",
+        "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1",
+    ];
+    for fen in valid {
+        assert_eq!(Position::from_str(fen).unwrap().is_valid(), Ok(()), "{fen}");
+    }
+
+    // Overlapping pieces can't come from a FEN
+    let mut pos = Position::default();
+    pos.pieces_bb[Color::BLACK][Piece::QUEEN] |= 1u64 << Square::D1;
+    assert_eq!(pos.is_valid(), Err(String::from("Two pieces on d1")));
+    let mut pos = Position::default();
+    pos.pieces_bb[Color::BLACK][Piece::KING] = 0;
+    assert_eq!(pos.is_valid(), Err(String::from("Black has 0 kings")));
+}
+
+#[test]
+fn pawn_captures_with_pins() {
+    // Moves of the piece on a square, sorted
+    let moves_from = |pos: &Position, sq: usize| {
+        let mut list = pos.legal();
+        list.filter_from(sq);
+        list.sort_by_key(|mv| mv.to_string());
+        list.to_string()
+    };
+
+    // The pawn on d4 is pinned by the bishop on c5: it can take it, but not the knight on e5
+    let pos = Position::from_str("4k3/8/8/2b1n3/3P4/4K3/8/8 w - - 0 1").unwrap();
+    assert_eq!(moves_from(&pos, Square::D4), "d4c5");
+    // Pinned by the bishop on b6, it can't move at all
+    let pos = Position::from_str("4k3/8/1b6/4n3/3P4/4K3/8/8 w - - 0 1").unwrap();
+    assert_eq!(moves_from(&pos, Square::D4), "");
+
+    // Same pin, but the rook on e8 gives check, so taking the pinner isn't legal anymore
+    let pos = Position::from_str("k3r3/8/8/2b5/3P4/4K3/8/8 w - - 0 1").unwrap();
+    assert!(pos.in_check());
+    assert_eq!(moves_from(&pos, Square::D4), "");
+    assert_eq!(pos.legal().count(), pos.legal_verified().count());
+
+    // A pinned pawn that promotes by taking its pinner
+    let pos = Position::from_str("1b2k3/2P5/3K4/8/8/8/8/8 w - - 0 1").unwrap();
+    assert_eq!(moves_from(&pos, Square::C7), "c7b8b c7b8n c7b8q c7b8r");
+
+    // Taking en passant would leave the king on a5 attacked by the rook on h5
+    let pos = Position::from_str("8/8/8/KPp4r/8/8/8/4k3 w - c6 0 2").unwrap();
+    assert_eq!(moves_from(&pos, Square::B5), "b5b6");
+    assert_eq!(pos.legal().count(), pos.legal_verified().count());
+}
+
+#[test]
+fn en_passant_from_both_sides() {
+    let pos = Position::from_str("4k3/8/8/3PpP2/8/8/8/4K3 w - e6 0 2").unwrap();
+    let mut list = pos.legal();
+    list.filter_to(Square::E6);
+    list.sort_by_key(|mv| mv.to_string());
+    assert_eq!(list.to_string(), "d5e6 f5e6");
+    assert_eq!(list.count_enpassants(), 2);
+    assert_eq!(pos.legal().count(), pos.legal_verified().count());
+
+    // The pawn that just moved gives check, and taking it is legal even with a rook on the rank
+    // of the king
+    let pos = Position::from_str("8/2p5/3p4/KPRr3k/5pP1/8/4P3/8 b - g3 0 3").unwrap();
+    assert!(pos
+        .legal()
+        .contains(Move::new(Square::F4, Square::G3, Move::EN_PASSANT)));
+    assert_eq!(pos.legal().count(), pos.legal_verified().count());
+
+    // Two pawns and two rooks on the rank: each en passant is generated once
+    let pos = Position::from_str("8/8/8/R2PpP1k/8/8/8/R3K3 w - e6 0 2").unwrap();
+    assert_eq!(pos.legal().count_enpassants(), 2);
+    assert_eq!(perft(pos.clone(), 2), perft_verified(pos, 2));
+}
+
+#[test]
+fn halfmove_clock() {
+    let hm_after = |fen: &str, mv: Move| {
+        let mut pos = Position::from_str(fen).unwrap();
+        assert!(pos.legal().contains(mv), "{mv}");
+        pos.make_move(mv);
+        pos.actual_state().hm
+    };
+    let fen = "r3k3/1P6/8/3Pp3/8/8/4P3/R3K1N1 w Qq e6 7 30";
+    // Pawn moves and captures
+    assert_eq!(
+        hm_after(fen, Move::new(Square::D5, Square::E6, Move::EN_PASSANT)),
+        0
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::E2, Square::E3, Move::QUIET)),
+        0
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)),
+        0
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::B7, Square::B8, Move::PR_Q)),
+        0
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::B7, Square::A8, Move::PC_N)),
+        0
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::A1, Square::A8, Move::CAPTURE)),
+        0
+    );
+    // Other moves
+    assert_eq!(
+        hm_after(fen, Move::new(Square::G1, Square::F3, Move::QUIET)),
+        8
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::E1, Square::C1, Move::CASTLE_000)),
+        8
+    );
+    assert_eq!(
+        hm_after(fen, Move::new(Square::A1, Square::A7, Move::QUIET)),
+        8
+    );
+}
+
+#[test]
+fn double_push_only_from_start_rank() {
+    // Pawns on e3 and d6 have two empty squares in front but already left their start rank, and
+    // the pawn on g3 is pinned on its file
+    for fen in [
+        "4k3/8/3p4/8/8/4P3/8/4K3 w - - 0 1",
+        "4k3/8/3p4/8/8/4P3/8/4K3 b - - 0 1",
+        "4k1r1/8/8/8/8/6P1/8/6K1 w - - 0 1",
+    ] {
+        let pos = Position::from_str(fen).unwrap();
+        let list = pos.legal();
+        assert_eq!(
+            list.into_iter()
+                .filter(|mv| mv.flag() == Move::DOUBLE_PUSH)
+                .count(),
+            0,
+            "{fen}"
+        );
+        assert_eq!(list.count(), pos.legal_verified().count(), "{fen}");
+    }
+
+    // From the start rank, the push needs both squares empty
+    let pos = Position::from_str("4k3/8/8/8/4n3/5b2/3PPP2/4K3 w - - 0 1").unwrap();
+    let doubles = pos
+        .legal()
+        .into_iter()
+        .filter(|mv| mv.flag() == Move::DOUBLE_PUSH);
+    assert_eq!(
+        doubles.map(|mv| mv.to_string()).collect::<Vec<_>>(),
+        ["d2d4"]
+    );
+    // A pawn pinned on its file can still push two squares
+    let pos = Position::from_str("4r1k1/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+    assert!(pos
+        .legal()
+        .contains(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH)));
+}
+
+#[test]
+fn castling_rights_transitions() {
+    let all = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    let promo = "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1";
+    let table = [
+        // Moving the king removes both rights, moving a rook only its side
+        (all, "e1d1", Move::QUIET, "kq"),
+        (all, "e1f2", Move::QUIET, "kq"),
+        (all, "e1g1", Move::CASTLE_00, "kq"),
+        (all, "e1c1", Move::CASTLE_000, "kq"),
+        (all, "h1g1", Move::QUIET, "Qkq"),
+        (all, "a1b1", Move::QUIET, "Kkq"),
+        // Capturing a rook on its square removes that side's right
+        (all, "h1h8", Move::CAPTURE, "Qq"),
+        (all, "a1a8", Move::CAPTURE, "Kk"),
+        (
+            "r3k2r/8/8/8/8/8/8/R3K1RR w KQkq - 0 1",
+            "g1g8",
+            Move::QUIET,
+            "KQkq",
+        ),
+        (
+            "r3k2r/8/8/8/8/1B6/8/R3K2R w KQkq - 0 1",
+            "b3a2",
+            Move::QUIET,
+            "KQkq",
+        ),
+        (
+            "r3k2r/8/8/8/8/8/8/R3K1BR w KQkq - 0 1",
+            "g1a7",
+            Move::QUIET,
+            "KQkq",
+        ),
+        (
+            "r3k2r/1B6/8/8/8/8/8/R3K2R w KQkq - 0 1",
+            "b7a8",
+            Move::CAPTURE,
+            "KQk",
+        ),
+        (
+            "r3k2r/6K1/8/8/8/8/8/R6R w kq - 0 1",
+            "g7h8",
+            Move::CAPTURE,
+            "q",
+        ),
+        // Promoting with a capture on a rook square, but not without one
+        (promo, "g7h8q", Move::PC_Q, "KQq"),
+        (promo, "b7a8n", Move::PC_N, "KQk"),
+        (promo, "g7g8q", Move::PR_Q, "KQkq"),
+        (
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R b KQkq - 0 1",
+            "g2h1r",
+            Move::PC_R,
+            "Qkq",
+        ),
+        (
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R b KQkq - 0 1",
+            "b2a1b",
+            Move::PC_B,
+            "Kkq",
+        ),
+        (
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+            "e8c8",
+            Move::CASTLE_000,
+            "KQ",
+        ),
+        (
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+            "a8a1",
+            Move::CAPTURE,
+            "Kk",
+        ),
+    ];
+    for (fen, uci, flag, rights) in table {
+        let mut pos = Position::from_str(fen).unwrap();
+        let mv = pos
+            .legal()
+            .into_iter()
+            .find(|mv| mv.to_string() == uci)
+            .unwrap();
+        assert_eq!(mv.flag(), flag, "{fen} {uci}");
+        pos.make_move(mv);
+        let castling = pos.fen().split(' ').nth(2).unwrap().to_string();
+        assert_eq!(castling, rights, "{fen} {uci}");
+        assert_eq!(pos.hash(true), pos.recompute_hash(), "{fen} {uci}");
+        pos.undo_move(mv);
+        assert_eq!(pos.fen(), fen);
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[test]
+fn wasm_position() {
+    use crate::wasm::WasmPosition;
+
+    let mut pos = WasmPosition::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+    assert_eq!(pos.legal_uci().len(), 6);
+    assert!(pos.legal_uci().contains(&"e2e4".to_string()));
+    pos.make_uci("e2e4").unwrap();
+    assert_eq!(pos.fen(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+    assert!(pos.undo());
+    assert!(!pos.undo());
+    assert_eq!(WasmPosition::new().legal_uci().len(), 20);
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn capi_round_trip() {
+    use crate::capi::*;
+
+    let fen = std::ffi::CString::new("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+    unsafe {
+        assert!(tc_position_from_fen(c"not a fen".as_ptr()).is_null());
+        let pos = tc_position_from_fen(fen.as_ptr());
+        let mut moves = [0u16; 218];
+        let count = tc_legal(pos, moves.as_mut_ptr(), moves.len());
+        assert_eq!(count, 6);
+        // A short buffer gets the first moves only
+        let mut short = [0u16; 2];
+        assert_eq!(tc_legal(pos, short.as_mut_ptr(), short.len()), 6);
+        assert_eq!(short, moves[..2]);
+
+        let e4 = Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH).to_raw();
+        assert!(moves[..count].contains(&e4));
+        assert!(!tc_make(
+            pos,
+            Move::new(Square::E2, Square::E5, Move::QUIET).to_raw()
+        ));
+        assert!(tc_make(pos, e4));
+        let mut buf = [0 as std::ffi::c_char; 128];
+        let len = tc_fen(pos, buf.as_mut_ptr(), buf.len());
+        let written = std::ffi::CStr::from_ptr(buf.as_ptr()).to_str().unwrap();
+        assert_eq!(written, "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+        assert_eq!(len, written.len());
+        // Truncated to the buffer, still nul-terminated
+        let mut small = [0 as std::ffi::c_char; 4];
+        assert_eq!(tc_fen(pos, small.as_mut_ptr(), small.len()), len);
+        assert_eq!(std::ffi::CStr::from_ptr(small.as_ptr()).to_str(), Ok("4k3"));
+
+        assert!(!tc_undo(pos, moves[0]));
+        assert!(tc_undo(pos, e4));
+        let len = tc_fen(pos, buf.as_mut_ptr(), buf.len());
+        assert_eq!(len, fen.as_bytes().len());
+        tc_position_free(pos);
+
+        let start = tc_position_new();
+        assert_eq!(tc_legal(start, moves.as_mut_ptr(), moves.len()), 20);
+        tc_position_free(start);
+        tc_position_free(std::ptr::null_mut());
+    }
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn python_module() {
+    use crate::python::PyPosition;
+    use pyo3::prelude::*;
+
+    let mut pos = PyPosition::new(Some("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1")).unwrap();
+    assert!(PyPosition::new(Some("not a fen")).is_err());
+    assert!(pos.make("a1a9").is_err());
+    pos.make("a1a8").unwrap();
+    assert!(pos.is_checkmate());
+    assert_eq!(pos.undo(), Some("a1a8".to_string()));
+    assert_eq!(pos.undo(), None);
+    assert_eq!(PyPosition::new(None).unwrap().perft(3), 8902);
+
+    Python::initialize();
+    Python::attach(|py| {
+        let module = pyo3::wrap_pymodule!(crate::python::turbochess)(py);
+        let pos = module.getattr(py, "Position").unwrap().call0(py).unwrap();
+        let legal: Vec<String> = pos.call_method0(py, "legal").unwrap().extract(py).unwrap();
+        assert_eq!(legal.len(), 20);
+    });
+}
+
+#[test]
+fn with_move_restores_position() {
+    let mut pos =
+        Position::from_str("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+            .unwrap();
+    let (fen, hash) = (pos.fen(), pos.hash(true));
+    for mv in pos.legal() {
+        let captured = pos.with_move(mv, |pos| {
+            // Nested moves are undone as well
+            if let Some(reply) = pos.legal().into_iter().next() {
+                pos.with_move(reply, |_| ());
+            }
+            pos.actual_state().captured
+        });
+        assert_eq!(captured.is_some(), mv.is_capture(), "{mv}");
+        assert_eq!(pos.fen(), fen, "{mv}");
+        assert_eq!(pos.hash(true), hash, "{mv}");
+    }
+}
+
+#[test]
+fn move_deltas() {
+    let pos = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let deltas = pos.move_deltas(Move::new(Square::E1, Square::G1, Move::CASTLE_00));
+    assert_eq!(deltas.count(), 4);
+    let squares: Vec<_> = deltas.iter().map(|c| c.square).collect();
+    assert_eq!(squares, [Square::E1, Square::G1, Square::H1, Square::F1]);
+
+    let pos = Position::from_str("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1").unwrap();
+    let deltas = pos.move_deltas(Move::new(Square::E5, Square::D6, Move::EN_PASSANT));
+    assert_eq!(
+        deltas.as_slice(),
+        [
+            SquareChange {
+                square: Square::E5,
+                before: Some((Piece::PAWN, Color::WHITE)),
+                after: None
+            },
+            SquareChange {
+                square: Square::D6,
+                before: None,
+                after: Some((Piece::PAWN, Color::WHITE))
+            },
+            SquareChange {
+                square: Square::D5,
+                before: Some((Piece::PAWN, Color::BLACK)),
+                after: None
+            },
+        ]
+    );
+
+    // The changes are exactly the squares that differ after making each move
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+        "4k3/8/8/2PpP3/8/8/8/4K3 w - d6 0 1",
+    ] {
+        let mut pos = Position::from_str(fen).unwrap();
+        for mv in pos.legal() {
+            let deltas = pos.move_deltas(mv);
+            let board = |pos: &Position| -> Vec<_> {
+                (0..64)
+                    .map(|sq| pos.piece_on(sq).zip(pos.color_on(sq)))
+                    .collect()
+            };
+            let before = board(&pos);
+            let after = pos.with_move(mv, |pos| board(pos));
+            let changed: Vec<_> = (0..64).filter(|&sq| before[sq] != after[sq]).collect();
+            let mut squares: Vec<_> = deltas.iter().map(|c| c.square).collect();
+            squares.sort();
+            assert_eq!(squares, changed, "{fen} {mv}");
+            for change in deltas.iter() {
+                assert_eq!(change.before, before[change.square], "{fen} {mv}");
+                assert_eq!(change.after, after[change.square], "{fen} {mv}");
+            }
+        }
+    }
+}
+
+#[test]
+fn grid() {
+    let rows: Vec<String> = Position::default()
+        .to_grid()
+        .iter()
+        .map(|row| row.iter().collect())
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            "rnbqkbnr", "pppppppp", "        ", "        ", "        ", "        ", "PPPPPPPP",
+            "RNBQKBNR"
+        ]
+    );
+    // Same orientation as the FEN
+    let pos = Position::from_str("8/5k2/8/3Pp3/8/8/1K6/8 w - e6 0 1").unwrap();
+    let grid = pos.to_grid();
+    assert_eq!(
+        (grid[1][5], grid[3][3], grid[3][4], grid[6][1]),
+        ('k', 'P', 'p', 'K')
+    );
+}
+
+#[test]
+fn highlighted_board() {
+    let pos = Position::from_str("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+    let board = pos.to_string_highlighting(Move::new(Square::E1, Square::G1, Move::CASTLE_00));
+    assert_eq!(
+        board,
+        "  +-----------------+\n\
+         8 | . . . . k . . . |\n\
+         7 | . . . . . . . . |\n\
+         6 | . . . . . . . . |\n\
+         5 | . . . . . . . . |\n\
+         4 | . . . . . . . . |\n\
+         3 | . . . . . . . . |\n\
+         2 | . . . . . . . . |\n\
+         1 | R . . .[K].[.]R |\n\
+         \x20 +-----------------+\n\
+         \x20   a b c d e f g h"
+    );
+    // Adjacent squares share a separator, and the edges stay aligned
+    let board = pos.to_string_highlighting(Move::new(Square::H1, Square::G1, Move::QUIET));
+    assert!(board.contains("1 | R . . . K .[.|R]|\n"));
+    assert_eq!(board.len(), pos.to_string().len());
+}
+
+#[test]
+fn evasions_match_verified() {
+    for fen in [
+        // Knight, pawn and slider checks
+        "4k3/8/8/8/8/3n4/8/R3K2R w KQ - 0 1",
+        "4k3/8/8/8/8/8/3p4/4K3 w - - 0 1",
+        "4k3/8/8/8/8/8/8/r3K2R w K - 0 1",
+        // Blocks with single and double pushes, and with a promotion
+        "4k3/8/8/8/7b/8/6P1/4K3 w - - 0 1",
+        "4k3/8/8/b7/8/8/1P6/4K3 w - - 0 1",
+        "4k3/8/8/b7/8/8/2P5/4K3 w - - 0 1",
+        "k3K2r/6P1/8/8/8/8/8/8 w - - 0 1",
+        "7K/8/8/8/8/8/1p6/R3k3 b - - 0 1",
+        // Capturing the checker by promotion and en passant, and an en passant that leaves the
+        // check on
+        "1r5k/P7/8/8/8/8/8/1K6 w - - 0 1",
+        "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        "8/8/7k/8/3Pp3/8/8/2B1K3 b - d3 0 1",
+        // A pinned piece can't block or capture
+        "4k3/4r3/8/8/8/8/2B5/r3K3 w - - 0 1",
+        "4k3/8/8/8/1b6/8/3N4/4K2r w - - 0 1",
+        // Double checks
+        "4k3/8/8/8/8/3n4/8/4K2r w - - 0 1",
+        "4k3/4r3/8/8/8/8/5p2/4K3 w - - 0 1",
+    ] {
+        let pos = Position::from_str(fen).unwrap();
+        assert!(pos.in_check(), "{fen}");
+        let mut legal = pos.legal().to_vec();
+        let mut verified = pos.legal_verified().to_vec();
+        legal.sort();
+        verified.sort();
+        assert_eq!(legal, verified, "{fen}");
+    }
+}
+
+#[test]
+fn has_legal_moves_agrees_with_legal() {
+    let mut fens = vec![
+        // Checkmates, stalemates and positions where only the king, a pinned rook, a pawn or an
+        // en passant capture can move
+        "R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1",
+        "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+        "k7/P7/K7/8/8/8/8/8 b - - 0 1",
+        "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1",
+        "k7/8/1K6/8/8/8/8/1Q5r b - - 0 1",
+        "7k/8/8/8/8/8/rr6/K7 w - - 0 1",
+        "r6k/1r6/8/8/R1b5/8/8/K7 w - - 0 1",
+        "1r5k/8/8/8/8/2n5/7P/K7 w - - 0 1",
+        "k7/8/8/8/8/1r5b/r5P1/K7 w - - 0 1",
+        "7k/8/8/8/8/1r6/1P6/K3q3 w - - 0 1",
+        "8/8/8/8/pP6/k7/2Q5/K7 b - b3 0 1",
+    ];
+    let start = Position::default();
+    let mut pos = start.clone();
+    let mut found = Vec::new();
+    // Positions along a few perft trees
+    for mv in start.legal() {
+        pos.make_move(mv);
+        for reply in pos.legal() {
+            pos.make_move(reply);
+            found.push(pos.fen());
+            pos.undo_move(reply);
+        }
+        pos.undo_move(mv);
+    }
+    fens.extend(found.iter().map(|f| f.as_str()));
+    for fen in fens {
+        let pos = Position::from_str(fen).unwrap();
+        let count = pos.legal().count();
+        assert_eq!(pos.has_legal_moves(), count != 0, "{fen}");
+        assert_eq!(pos.is_checkmate(), count == 0 && pos.in_check(), "{fen}");
+        assert_eq!(pos.is_stalemate(), count == 0 && !pos.in_check(), "{fen}");
+    }
+}
+
+#[test]
+fn polyglot_keys() {
+    // The test positions of the Polyglot book format, with the en passant square counted only
+    // after c2c4, where the pawn on b4 could capture
+    for (moves, key) in [
+        ("", 0x463b96181691fc9c),
+        ("e2e4", 0x823c9b50fd114196),
+        ("e2e4 d7d5", 0x0756b94461c50fb0),
+        ("e2e4 d7d5 e4e5", 0x662fafb965db29d4),
+        ("e2e4 d7d5 e4e5 f7f5", 0x22a48b5a8e47ff78),
+        ("e2e4 d7d5 e4e5 f7f5 e1e2", 0x652a607ca3f242c1),
+        ("e2e4 d7d5 e4e5 f7f5 e1e2 e8f7", 0x00fdd303c946bdd9),
+        ("a2a4 b7b5 h2h4 b5b4 c2c4", 0x3c8123ea7b067637),
+        ("a2a4 b7b5 h2h4 b5b4 c2c4 b4c3 a1a3", 0x5c3f9b829b279560),
+    ] {
+        let mut pos = Position::default();
+        for uci in moves.split_whitespace() {
+            let mv = pos
+                .legal()
+                .into_iter()
+                .find(|mv| mv.to_string() == uci)
+                .unwrap();
+            pos.make_move(mv);
+        }
+        assert_eq!(pos.polyglot_key(), key, "{moves}");
+    }
+}
+
+#[test]
+fn polyglot_book() {
+    use crate::PolyglotBook;
+
+    // Polyglot moves: destination in bits 0-5, start in bits 6-11 and the promotion in 12-14
+    let encode = |from: usize, to: usize, promo: u16| promo << 12 | (from as u16) << 6 | to as u16;
+    let start = Position::default();
+    let castling = Position::from_str("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    let promotion = Position::from_str("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let mut entries = vec![
+        (start.polyglot_key(), encode(Square::E2, Square::E4, 0), 10),
+        (start.polyglot_key(), encode(Square::D2, Square::D4, 0), 5),
+        // Not legal, as from another position with the same key
+        (start.polyglot_key(), encode(Square::E2, Square::E5, 0), 1),
+        (
+            castling.polyglot_key(),
+            encode(Square::E1, Square::H1, 0),
+            3,
+        ),
+        (
+            castling.polyglot_key(),
+            encode(Square::E1, Square::A1, 0),
+            2,
+        ),
+        (
+            promotion.polyglot_key(),
+            encode(Square::A7, Square::A8, 4),
+            7,
+        ),
+        (0, encode(Square::G1, Square::F3, 0), 1),
+        (u64::MAX, encode(Square::G1, Square::F3, 0), 1),
+    ];
+    entries.sort_by_key(|&(key, _, _)| key);
+    let mut bytes = Vec::new();
+    for (key, mv, weight) in entries {
+        bytes.extend(key.to_be_bytes());
+        bytes.extend(u16::to_be_bytes(mv));
+        bytes.extend(u16::to_be_bytes(weight));
+        bytes.extend([0; 4]);
+    }
+    let path = std::env::temp_dir().join(format!("turbochess-book-{}.bin", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+    let book = PolyglotBook::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(book.len(), 8);
+    assert_eq!(
+        book.lookup(&start),
+        [
+            (Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH), 10),
+            (Move::new(Square::D2, Square::D4, Move::DOUBLE_PUSH), 5),
+        ]
+    );
+    assert_eq!(
+        book.lookup(&castling),
+        [
+            (Move::new(Square::E1, Square::G1, Move::CASTLE_00), 3),
+            (Move::new(Square::E1, Square::C1, Move::CASTLE_000), 2),
+        ]
+    );
+    assert_eq!(
+        book.lookup(&promotion),
+        [(Move::new(Square::A7, Square::A8, Move::PR_Q), 7)]
+    );
+    assert!(book
+        .lookup(&Position::from_str("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap())
+        .is_empty());
+    assert!(PolyglotBook::from_bytes(&bytes[..20]).is_err());
+}
+
+#[test]
+fn endgame_types() {
+    use crate::types::Endgame;
+
+    for (fen, endgame) in [
+        ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", Endgame::KvK),
+        ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", Endgame::KPvK),
+        ("4k3/4p3/8/8/8/8/8/4K3 w - - 0 1", Endgame::KPvK),
+        ("4k3/8/8/8/8/8/8/2B1K1N1 w - - 0 1", Endgame::KBNvK),
+        ("4k3/8/8/8/8/8/8/2BBK3 w - - 0 1", Endgame::KBBvK),
+        ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", Endgame::KRvK),
+        ("3qk3/8/8/8/8/8/8/4K3 w - - 0 1", Endgame::KQvK),
+        ("4k3/8/8/8/8/8/8/1n2K2R w - - 0 1", Endgame::KRvKN),
+        ("4k3/8/8/8/8/8/8/1N2K2r b - - 0 1", Endgame::KRvKN),
+        ("r3k3/8/8/8/8/8/8/4K2R w - - 0 1", Endgame::KRvKR),
+        // The Lucena and Philidor positions
+        ("1K1k4/1P6/8/8/8/8/r7/2R5 w - - 0 1", Endgame::KRPvKR),
+        ("4k3/R7/8/4PK2/8/8/8/r7 b - - 0 1", Endgame::KRPvKR),
+        ("4k3/8/8/8/8/8/8/3QK2r w - - 0 1", Endgame::KQvKR),
+        ("3qk3/8/8/8/8/8/8/3QK3 w - - 0 1", Endgame::KQvKQ),
+        // More material, or the stronger side with a pawn too many
+        ("4k3/8/8/8/8/8/4PP2/4K3 w - - 0 1", Endgame::Other),
+        ("r3k3/7p/8/8/8/8/4P3/4K2R w - - 0 1", Endgame::Other),
+        (Position::START_FEN, Endgame::Other),
+    ] {
+        assert_eq!(
+            Position::from_str(fen).unwrap().endgame_type(),
+            endgame,
+            "{fen}"
+        );
+    }
+}
+
+#[test]
+fn repetition_ignores_unusable_en_passant() {
+    use crate::game::GameResult;
+
+    // The first time is right after the double push, with no pawn that could take en passant
+    let mut pos = Position::default();
+    for uci in "e2e4 g8f6 g1f3 f6g8 f3g1 g8f6 g1f3 f6g8 f3g1".split(' ') {
+        let mv = pos
+            .legal()
+            .into_iter()
+            .find(|mv| mv.to_string() == uci)
+            .unwrap();
+        pos.make_move(mv);
+    }
+    assert_eq!(pos.repetition_count(), 3);
+    assert_eq!(pos.repetition_count_full(), 3);
+    assert_eq!(GameResult::of(&pos), GameResult::Draw);
+
+    // A capturable en passant square still tells the positions apart
+    let mut pos = Position::from_str("4k3/8/8/8/5p2/8/4P1N1/4K3 w - - 0 1").unwrap();
+    for mv in [
+        Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH),
+        Move::new(Square::E8, Square::D8, Move::QUIET),
+        Move::new(Square::G2, Square::H4, Move::QUIET),
+        Move::new(Square::D8, Square::E8, Move::QUIET),
+        Move::new(Square::H4, Square::G2, Move::QUIET),
+    ] {
+        pos.make_move(mv);
+    }
+    assert_eq!(pos.repetition_count(), 1);
+}
+
+#[test]
+fn failed_set_fen_leaves_position() {
+    let mut pos = Position::default();
+    pos.make_move(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH));
+    let (fen, hash) = (pos.fen(), pos.hash(true));
+    for bad in [
+        "8/8/8 w - - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNRR w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 x",
+    ] {
+        assert!(pos.set_fen(bad).is_err(), "{bad}");
+        assert_eq!(pos.fen(), fen, "{bad}");
+        assert_eq!(pos.hash(true), hash, "{bad}");
+        assert_eq!(pos.legal().count(), 20, "{bad}");
+    }
+    assert_eq!(
+        pos.undo(),
+        Some(Move::new(Square::E2, Square::E4, Move::DOUBLE_PUSH))
+    );
+}
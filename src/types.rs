@@ -1,7 +1,13 @@
+use alloc::{format, string::String, vec::Vec};
 use core::iter::Rev;
-use std::{fmt, ops::Neg};
+use core::ops::Range;
+use core::{fmt, ops::Neg};
 
-/// Represents a move
+use crate::lookup::ADJACENT_FILES;
+use crate::Position;
+
+/// Represents a move, packed in a `u16`: the start square in bits 0-5, the destination square
+/// in bits 6-11 and the flag in bits 12-15
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Move(u16);
 
@@ -21,7 +27,13 @@ impl Move {
     pub const PC_B: usize = 11;
     pub const PC_R: usize = 12;
     pub const PC_Q: usize = 13;
+    /// Flag reserved for [Move::NULL], never produced by the move generator
+    pub const NULL_FLAG: usize = 15;
+    /// Encodes a1a1 as a quiet move, so it can't be told apart from a real move. Use
+    /// [Move::NULL] as a placeholder instead
     pub const EMPTY: Self = Self(0);
+    /// A placeholder that isn't a move, printed as `0000` like in UCI
+    pub const NULL: Self = Self((Self::NULL_FLAG as u16) << 12);
 
     const FROM_MASK: u16 = 0b0000000000111111;
     const TO_MASK: u16 = 0b0000111111000000;
@@ -32,6 +44,16 @@ impl Move {
         Self((from | to << 6 | flag << 12) as u16)
     }
 
+    /// Gets the raw encoding of the move (see [Move] for the layout)
+    pub fn to_raw(&self) -> u16 {
+        self.0
+    }
+
+    /// Creates a move from its raw encoding (see [Move] for the layout). The bits aren't checked
+    pub fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
     /// Gets the start square of the move
     pub fn from(&self) -> usize {
         (self.0 & Self::FROM_MASK) as usize
@@ -57,10 +79,44 @@ impl Move {
             || flag == Move::PC_R
             || flag == Move::PC_Q
     }
+
+    /// Checks that the move is the null placeholder
+    pub fn is_null(&self) -> bool {
+        self.flag() == Move::NULL_FLAG
+    }
+
+    /// Checks that the move is a promotion
+    pub fn is_promotion(&self) -> bool {
+        self.flag() >= Move::PR_N && self.flag() <= Move::PC_Q
+    }
+
+    /// Checks that the move is a castle, to either side
+    pub fn is_castle(&self) -> bool {
+        self.flag() == Move::CASTLE_00 || self.flag() == Move::CASTLE_000
+    }
+
+    /// Checks that the move is an en passant capture
+    pub fn is_en_passant(&self) -> bool {
+        self.flag() == Move::EN_PASSANT
+    }
+
+    /// Gets the piece the pawn promotes to, if the move is a promotion
+    pub fn promotion_piece(&self) -> Option<usize> {
+        match self.flag() {
+            Move::PR_N | Move::PC_N => Some(Piece::KNIGHT),
+            Move::PR_B | Move::PC_B => Some(Piece::BISHOP),
+            Move::PR_R | Move::PC_R => Some(Piece::ROOK),
+            Move::PR_Q | Move::PC_Q => Some(Piece::QUEEN),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Move {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_null() {
+            return write!(f, "0000");
+        }
         write!(
             f,
             "{}{}",
@@ -68,19 +124,133 @@ impl fmt::Display for Move {
             Square::to_string(self.to())
         )
         .unwrap();
-        if self.flag() == Move::PR_N || self.flag() == Move::PC_N {
-            write!(f, "n").unwrap();
+        if let Some(p) = self.promotion_piece() {
+            write!(f, "{}", Piece::to_char(p)).unwrap();
         }
-        if self.flag() == Move::PR_B || self.flag() == Move::PC_B {
-            write!(f, "b").unwrap();
+        Ok(())
+    }
+}
+
+/// Represents why a move couldn't be made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    /// There is no piece on the start square
+    EmptySquare,
+    /// The piece on the start square belongs to the opponent
+    NotYourPiece,
+    /// The move isn't legal in the position
+    Illegal,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoveError::EmptySquare => write!(f, "There is no piece on the start square"),
+            MoveError::NotYourPiece => write!(f, "The piece on the start square isn't yours"),
+            MoveError::Illegal => write!(f, "The move is illegal"),
         }
-        if self.flag() == Move::PR_R || self.flag() == Move::PC_R {
-            write!(f, "r").unwrap();
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MoveError {}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Move {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes a move from its UCI string. As there is no board context, the flag is only a best
+/// effort (quiet or promotion), so the move must be validated against a position before using it
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Move {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uci = String::deserialize(deserializer)?;
+        let bytes = uci.as_bytes();
+        let valid_square = |f: u8, r: u8| (b'a'..=b'h').contains(&f) && (b'1'..=b'8').contains(&r);
+        if (bytes.len() != 4 && bytes.len() != 5)
+            || !valid_square(bytes[0], bytes[1])
+            || !valid_square(bytes[2], bytes[3])
+        {
+            return Err(serde::de::Error::custom(format!(
+                "Invalid UCI move '{uci}'"
+            )));
         }
-        if self.flag() == Move::PR_Q || self.flag() == Move::PC_Q {
-            write!(f, "q").unwrap();
+        let flag = match bytes.get(4) {
+            None => Move::QUIET,
+            Some(b'n') => Move::PR_N,
+            Some(b'b') => Move::PR_B,
+            Some(b'r') => Move::PR_R,
+            Some(b'q') => Move::PR_Q,
+            Some(_) => {
+                return Err(serde::de::Error::custom(format!(
+                    "Invalid UCI move '{uci}'"
+                )));
+            }
+        };
+        Ok(Move::new(
+            Square::from_str(&uci[0..2]),
+            Square::from_str(&uci[2..4]),
+            flag,
+        ))
+    }
+}
+
+/// A square changed by a move, with the piece and color on it before and after the move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SquareChange {
+    pub square: usize,
+    pub before: Option<(usize, usize)>,
+    pub after: Option<(usize, usize)>,
+}
+
+/// The squares changed by a move. There are 2 for most moves, 3 for en passant and 4 for castling
+#[derive(Debug, Clone, Copy)]
+pub struct MoveDeltas {
+    array: [SquareChange; 4],
+    len: usize,
+}
+
+impl MoveDeltas {
+    /// Creates an empty list
+    pub(crate) fn new() -> Self {
+        let empty = SquareChange {
+            square: 0,
+            before: None,
+            after: None,
+        };
+        Self {
+            array: [empty; 4],
+            len: 0,
         }
-        Ok(())
+    }
+    /// Adds a change
+    pub(crate) fn add(
+        &mut self,
+        square: usize,
+        before: Option<(usize, usize)>,
+        after: Option<(usize, usize)>,
+    ) {
+        self.array[self.len] = SquareChange {
+            square,
+            before,
+            after,
+        };
+        self.len += 1;
+    }
+    /// Gets the number of changed squares
+    pub fn count(&self) -> usize {
+        self.len
+    }
+    /// Gets the changes as a slice
+    pub fn as_slice(&self) -> &[SquareChange] {
+        &self.array[..self.len]
+    }
+    /// Iterates over the changes
+    pub fn iter(&self) -> core::slice::Iter<'_, SquareChange> {
+        self.as_slice().iter()
     }
 }
 
@@ -95,17 +265,20 @@ impl MoveList {
     /// Creates a new MoveList
     pub fn new() -> Self {
         Self {
-            array: [Move::EMPTY; 218],
+            array: [Move::NULL; 218],
             len: 0,
         }
     }
     /// Adds a new move by putting its parameters
     pub fn add(&mut self, from: usize, to: usize, flag: usize) {
-        self.array[self.len] = Move::new(from, to, flag);
-        self.len += 1
+        self.add_raw(Move::new(from, to, flag));
     }
     /// Adds a new move
     pub fn add_raw(&mut self, mv: Move) {
+        debug_assert!(
+            self.len < 218,
+            "MoveList is full, a position can't have more than 218 moves"
+        );
         self.array[self.len] = mv;
         self.len += 1
     }
@@ -150,57 +323,62 @@ impl MoveList {
     pub fn count(self) -> usize {
         self.len
     }
+    /// Sorts the moves by a key. The sort is stable, so moves with the same key keep their order
+    pub fn sort_by_key<K: Ord>(&mut self, mut f: impl FnMut(Move) -> K) {
+        self.array[..self.len].sort_by_key(|&mv| f(mv));
+    }
+    /// Sorts the moves so the captures come first, ordered by most valuable victim and then least
+    /// valuable attacker. The rest of the moves keep their order
+    pub fn sort_mvv_lva(&mut self, pos: &Position) {
+        self.sort_by_key(|mv| {
+            if !mv.is_capture() {
+                return (1, 0, 0);
+            }
+            let victim = if mv.is_en_passant() {
+                Piece::PAWN
+            } else {
+                pos.piece_on(mv.to()).unwrap()
+            };
+            (0, Piece::KING - victim, pos.piece_on(mv.from()).unwrap())
+        });
+    }
+    /// Gets the moves as a slice, without copying them
+    pub fn as_slice(&self) -> &[Move] {
+        &self.array[..self.len]
+    }
+    /// Copies the moves into a vector
+    pub fn to_vec(&self) -> Vec<Move> {
+        self.as_slice().to_vec()
+    }
+    /// Checks if the list contains a move
+    pub fn contains(&self, mv: Move) -> bool {
+        self.as_slice().contains(&mv)
+    }
+    /// Finds the move going from a square to another, whatever its flag is. Promotions only match
+    /// when `promo` is the piece they promote to, and other moves only match when `promo` is `None`
+    pub fn find_from_to(&self, from: usize, to: usize, promo: Option<usize>) -> Option<Move> {
+        self.into_iter()
+            .copied()
+            .find(|mv| mv.from() == from && mv.to() == to && mv.promotion_piece() == promo)
+    }
     /// Counts all the promotions
     pub fn count_promotions(&self) -> usize {
-        let mut count = 0;
-        for m in &self.array {
-            if m == &Move::EMPTY {
-                break;
-            }
-            if m.flag() >= Move::PR_N && m.flag() <= Move::PC_Q {
-                count += 1;
-            }
-        }
-        count
+        self.as_slice().iter().filter(|m| m.is_promotion()).count()
     }
-    /// Counts all the captures
+    /// Counts all the captures, except the en passants
     pub fn count_captures(&self) -> usize {
-        let mut count = 0;
-        for m in &self.array {
-            if m == &Move::EMPTY {
-                break;
-            }
-            if m.flag() == Move::CAPTURE || (m.flag() >= Move::PC_N && m.flag() <= Move::PC_Q) {
-                count += 1;
-            }
-        }
-        count
+        self.as_slice()
+            .iter()
+            .filter(|m| m.is_capture() && !m.is_en_passant())
+            .count()
     }
     /// Counts all the en passants
     pub fn count_enpassants(&self) -> usize {
-        let mut count = 0;
-        for m in &self.array {
-            if m == &Move::EMPTY {
-                break;
-            }
-            if m.flag() == Move::EN_PASSANT {
-                count += 1;
-            }
-        }
-        count
+        self.as_slice().iter().filter(|m| m.is_en_passant()).count()
     }
     /// Counts all the castles
     pub fn count_castles(&self) -> usize {
-        let mut count = 0;
-        for m in &self.array {
-            if m == &Move::EMPTY {
-                break;
-            }
-            if m.flag() == Move::CASTLE_00 || m.flag() == Move::CASTLE_000 {
-                count += 1;
-            }
-        }
-        count
+        self.as_slice().iter().filter(|m| m.is_castle()).count()
     }
     /// Iterates over the move list in reverse
     pub fn rev(self) -> Rev<MoveListIterator> {
@@ -211,69 +389,62 @@ impl MoveList {
         }
         .rev()
     }
-    /// Filters all moves that are from a square
-    pub fn filter_from(&mut self, from: usize) {
+    /// Keeps only the moves that satisfy the predicate, in the same order
+    pub fn retain(&mut self, f: impl Fn(Move) -> bool) {
         let mut i = 0;
         let mut j = 0;
         while i < self.len {
-            if self.array[i].from() == from {
+            if f(self.array[i]) {
                 self.array.swap(i, j);
                 i += 1;
                 j += 1;
             } else {
-                self.array[i] = Move::EMPTY;
+                self.array[i] = Move::NULL;
                 i += 1;
             }
         }
         self.len = j;
     }
+    /// Filters all moves that are from a square
+    pub fn filter_from(&mut self, from: usize) {
+        self.retain(|mv| mv.from() == from);
+    }
     /// Filters all moves that are to a square
     pub fn filter_to(&mut self, to: usize) {
-        let mut i = 0;
-        let mut j = 0;
-        while i < self.len {
-            if self.array[i].to() == to {
-                self.array.swap(i, j);
-                i += 1;
-                j += 1;
-            } else {
-                self.array[i] = Move::EMPTY;
-                i += 1;
-            }
-        }
-        self.len = j;
+        self.retain(|mv| mv.to() == to);
     }
     /// Filters all moves that are from a bitboard
     pub fn filter_from_bb(&mut self, from: u64) {
-        let mut i = 0;
-        let mut j = 0;
-        while i < self.len {
-            if (1u64 << self.array[i].from()) & from != 0 {
-                self.array.swap(i, j);
-                i += 1;
-                j += 1;
-            } else {
-                self.array[i] = Move::EMPTY;
-                i += 1;
-            }
-        }
-        self.len = j;
+        self.retain(|mv| (1u64 << mv.from()) & from != 0);
     }
     /// Filters all moves that are to a bitboard
     pub fn filter_to_bb(&mut self, to: u64) {
-        let mut i = 0;
-        let mut j = 0;
-        while i < self.len {
-            if (1u64 << self.array[i].to()) & to != 0 {
-                self.array.swap(i, j);
-                i += 1;
-                j += 1;
-            } else {
-                self.array[i] = Move::EMPTY;
-                i += 1;
-            }
+        self.retain(|mv| (1u64 << mv.to()) & to != 0);
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for MoveList {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.array[..self.len])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MoveList {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let moves = alloc::vec::Vec::<Move>::deserialize(deserializer)?;
+        if moves.len() > 218 {
+            return Err(serde::de::Error::invalid_length(
+                moves.len(),
+                &"at most 218 moves",
+            ));
         }
-        self.len = j;
+        let mut list = MoveList::new();
+        for mv in moves {
+            list.add_raw(mv);
+        }
+        Ok(list)
     }
 }
 
@@ -290,6 +461,42 @@ impl IntoIterator for MoveList {
     }
 }
 
+/// Prints the moves in UCI separated by spaces. Use the alternate flag (`{:#}`) to print one per
+/// line
+impl fmt::Display for MoveList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if f.alternate() { "\n" } else { " " };
+        for (i, mv) in self.into_iter().enumerate() {
+            if i > 0 {
+                write!(f, "{separator}")?;
+            }
+            write!(f, "{mv}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<Move> for MoveList {
+    /// Builds a list from the moves of an iterator. Panics if there are more than 218 of them
+    fn from_iter<I: IntoIterator<Item = Move>>(iter: I) -> Self {
+        let mut list = MoveList::new();
+        for mv in iter {
+            assert!(list.len < 218, "Too many moves for a MoveList");
+            list.add_raw(mv);
+        }
+        list
+    }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = core::slice::Iter<'a, Move>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
 pub struct MoveListIterator {
     list: MoveList,
     index: usize,
@@ -339,6 +546,9 @@ impl Piece {
     pub const ROOK: usize = 3;
     pub const QUEEN: usize = 4;
     pub const KING: usize = 5;
+    /// Material value of every piece type in centipawns. The king has no value as it can't be
+    /// traded
+    pub const VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0];
 }
 
 impl Piece {
@@ -364,6 +574,99 @@ impl Piece {
             _ => unreachable!("Invalid piece type"),
         }
     }
+    pub fn to_unicode(p: usize, color: usize) -> char {
+        let glyphs = if color == Color::WHITE {
+            ['♙', '♘', '♗', '♖', '♕', '♔']
+        } else {
+            ['♟', '♞', '♝', '♜', '♛', '♚']
+        };
+        glyphs[p]
+    }
+}
+
+/// Typed version of the [Piece] constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(usize)]
+pub enum PieceType {
+    Pawn = 0,
+    Knight = 1,
+    Bishop = 2,
+    Rook = 3,
+    Queen = 4,
+    King = 5,
+}
+
+impl PieceType {
+    /// All the piece types, indexed by their [Piece] constant
+    pub const ALL: [Self; 6] = [
+        Self::Pawn,
+        Self::Knight,
+        Self::Bishop,
+        Self::Rook,
+        Self::Queen,
+        Self::King,
+    ];
+
+    /// Gets the piece type from a [Piece] constant
+    pub fn from_index(p: usize) -> Self {
+        Self::ALL[p]
+    }
+
+    /// Gets the [Piece] constant of the piece type
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Typed version of the [Color] constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(usize)]
+pub enum Side {
+    White = 0,
+    Black = 1,
+}
+
+impl Side {
+    /// Gets the side from a [Color] constant
+    pub fn from_index(c: usize) -> Self {
+        if c == Color::WHITE {
+            Self::White
+        } else {
+            Self::Black
+        }
+    }
+
+    /// Gets the [Color] constant of the side
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Material classification of an endgame, named with the stronger side first no matter its
+/// color. New endings can be added, so matches need a wildcard arm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Endgame {
+    KvK,
+    KPvK,
+    KNvK,
+    KBvK,
+    KNNvK,
+    KBNvK,
+    KBBvK,
+    KRvK,
+    KQvK,
+    KPvKP,
+    KRvKP,
+    KQvKP,
+    KRvKN,
+    KRvKB,
+    KRvKR,
+    KRPvKR,
+    KQvKR,
+    KQvKQ,
+    /// Any other material
+    Other,
 }
 
 /// Represents a direction
@@ -438,7 +741,11 @@ impl BitBoard {
     pub const RANK_7: u64 = 0xff000000000000;
     pub const RANK_8: u64 = 0xff00000000000000;
 
+    pub const LIGHT_SQUARES: u64 = 0x55aa55aa55aa55aa;
+    pub const DARK_SQUARES: u64 = 0xaa55aa55aa55aa55;
+
     /// Prints a bitboard
+    #[cfg(feature = "std")]
     pub fn print(mut bb: u64) {
         println!("  +-----------------+");
         let mut rank = [0; 8];
@@ -492,10 +799,53 @@ impl BitBoard {
         } else {
             8 - rank
         };
-        Self::RANK_1 << (num * 8)
+        Self::rank(num)
+    }
+
+    /// Gets the mask of a file, from 0 (a) to 7 (h)
+    pub fn file(f: usize) -> u64 {
+        Self::FILE_A << f
+    }
+
+    /// Gets the mask of a rank, from 0 (first rank) to 7 (eighth rank)
+    pub fn rank(r: usize) -> u64 {
+        Self::RANK_1 << (r * 8)
+    }
+
+    /// Gets the mask of the files next to a file, from 0 (a) to 7 (h)
+    pub fn adjacent_files(f: usize) -> u64 {
+        ADJACENT_FILES[f]
+    }
+
+    /// Iterates over the squares set in a bitboard, from a1 to h8
+    pub fn squares(bb: u64) -> BitBoardSquareIter {
+        BitBoardSquareIter(bb)
+    }
+}
+
+pub struct BitBoardSquareIter(u64);
+
+impl Iterator for BitBoardSquareIter {
+    type Item = usize;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0 == 0 {
+            return None;
+        }
+        let s = self.0.bit_scan();
+        self.0 = self.0.pop_lsb();
+        Some(s)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.0.bit_count();
+        (count, Some(count))
     }
 }
 
+impl ExactSizeIterator for BitBoardSquareIter {}
+
 pub struct BitBoardSubsetIter {
     set: u64,
     subset: u64,
@@ -586,6 +936,38 @@ impl Square {
     pub const G8: usize = 62;
     pub const H8: usize = 63;
 
+    /// Iterates over all the squares, from a1 to h8
+    pub fn iter() -> Range<usize> {
+        0..64
+    }
+
+    /// Gets the file of a square, from 0 (a) to 7 (h)
+    pub fn file(sq: usize) -> usize {
+        sq % 8
+    }
+
+    /// Gets the rank of a square, from 0 (first rank) to 7 (eighth rank)
+    pub fn rank(sq: usize) -> usize {
+        sq / 8
+    }
+
+    /// Checks if a square is light (h1 is light, a1 is dark)
+    pub fn is_light(sq: usize) -> bool {
+        (Self::file(sq) + Self::rank(sq)) % 2 == 1
+    }
+
+    /// Gets the number of king moves between two squares (Chebyshev distance)
+    pub fn distance(a: usize, b: usize) -> usize {
+        Self::file(a)
+            .abs_diff(Self::file(b))
+            .max(Self::rank(a).abs_diff(Self::rank(b)))
+    }
+
+    /// Gets the number of rook steps between two squares (Manhattan distance)
+    pub fn manhattan(a: usize, b: usize) -> usize {
+        Self::file(a).abs_diff(Self::file(b)) + Self::rank(a).abs_diff(Self::rank(b))
+    }
+
     /// Gets a square from a string
     pub fn from_str(sq: &str) -> usize {
         let file = sq.chars().nth(0).unwrap().to_ascii_lowercase() as usize - 'a' as usize;
@@ -0,0 +1,77 @@
+//! WebAssembly bindings, enabled with the `wasm` feature. They are a thin layer over [Position]
+//! that exchanges moves and positions as UCI and FEN strings
+//!
+//! ```js
+//! import init, { Position } from "./pkg/turbochess.js";
+//!
+//! await init();
+//! const pos = Position.fromFen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+//! console.log(pos.legalUci()); // ["a2a3", "b2b3", ...]
+//! pos.makeUci("e2e4");
+//! console.log(pos.fen()); // "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+//! ```
+
+use alloc::{string::String, string::ToString, vec::Vec};
+use core::str::FromStr;
+
+use wasm_bindgen::prelude::*;
+
+use crate::Position;
+
+/// A position handle for JavaScript
+#[wasm_bindgen(js_name = Position)]
+pub struct WasmPosition {
+    pos: Position,
+}
+
+#[wasm_bindgen(js_class = Position)]
+impl WasmPosition {
+    /// Creates the initial position
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            pos: Position::default(),
+        }
+    }
+    /// Creates a position from a FEN, throwing if it's invalid
+    #[wasm_bindgen(js_name = fromFen)]
+    pub fn from_fen(fen: &str) -> Result<WasmPosition, JsError> {
+        let pos = Position::from_str(fen).map_err(|e| JsError::new(&e))?;
+        Ok(Self { pos })
+    }
+    /// Gets the legal moves in UCI
+    #[wasm_bindgen(js_name = legalUci)]
+    pub fn legal_uci(&self) -> Vec<String> {
+        self.pos
+            .legal()
+            .into_iter()
+            .map(|mv| mv.to_string())
+            .collect()
+    }
+    /// Makes a move given in UCI, throwing if it isn't legal
+    #[wasm_bindgen(js_name = makeUci)]
+    pub fn make_uci(&mut self, uci: &str) -> Result<(), JsError> {
+        let mv = self
+            .pos
+            .legal()
+            .into_iter()
+            .find(|mv| mv.to_string() == uci)
+            .ok_or_else(|| JsError::new(&alloc::format!("Illegal move '{uci}'")))?;
+        self.pos.make_move(mv);
+        Ok(())
+    }
+    /// Undoes the last move, returning false if there is none
+    pub fn undo(&mut self) -> bool {
+        self.pos.undo().is_some()
+    }
+    /// Gets the FEN of the position
+    pub fn fen(&self) -> String {
+        self.pos.fen()
+    }
+}
+
+impl Default for WasmPosition {
+    fn default() -> Self {
+        Self::new()
+    }
+}